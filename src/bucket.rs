@@ -1,25 +1,50 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
 use colored::*;
+use serde::Serialize;
 
+use crate::export::ExportedRecord;
 use crate::format::RecordFormattingOptions;
 use crate::record::Record;
 use crate::time::Mean;
-use crate::time::{Duration, Interval, Timestamp};
+use crate::time::{Calendar, Duration, Interval, Span, Timestamp};
 
-pub struct RecordBucket(pub Vec<Record>, Interval, bool);
+pub struct RecordBucket(pub Vec<Record>, Span, Calendar, bool);
+
+/// A bucket along with its stats, as printed by `punch show --format json`. Durations are
+/// in seconds.
+#[derive(Serialize)]
+pub struct BucketSummary<'a> {
+    name: String,
+    interval: String,
+    key: u32,
+    count: usize,
+    sum: usize,
+    rounded_sum: usize,
+    sum_of_rounded: usize,
+    avg: usize,
+    records: Vec<ExportedRecord<'a>>,
+}
 
 impl fmt::Display for RecordBucket {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let opts = RecordFormattingOptions {
             align_with_n_records: self.size(),
-            precise: self.2,
+            precise: self.3,
             timezone: true,
             rounding_opts: Default::default(),
+            projects: Default::default(),
         };
 
-        writeln!(f, "{}", self.name().bold().underline().to_string())?;
+        writeln!(f, "{}", self.name().bold().underline())?;
         writeln!(f, "{}", self.format_stats_with(&opts))?;
+        if let Some(tags) = self.format_tags_with(&opts) {
+            writeln!(f, "{}", tags)?;
+        }
+        if let Some(projects) = self.format_projects_with(&opts) {
+            writeln!(f, "{}", projects)?;
+        }
 
         for record in self.0.iter() {
             writeln!(f, "{}", record.format_with(&opts))?;
@@ -30,8 +55,8 @@ impl fmt::Display for RecordBucket {
 }
 
 impl RecordBucket {
-    pub fn new(interval: Interval, precise: bool) -> Self {
-        RecordBucket(Vec::new(), interval, precise)
+    pub fn new(span: Span, calendar: Calendar, precise: bool) -> Self {
+        RecordBucket(Vec::new(), span, calendar, precise)
     }
     pub fn add(&mut self, record: Record) {
         self.0.push(record)
@@ -39,8 +64,29 @@ impl RecordBucket {
     pub fn name(&self) -> String {
         let date = (self.0)[0].start;
 
-        match self.1 {
-            Interval::Second => unreachable!(),
+        if self.1.length > 1 {
+            let start = date.start_of_span(self.1, 0, &self.2);
+            let end = date.start_of_span(self.1, 1, &self.2);
+
+            return match self.1.interval {
+                Interval::Second | Interval::Minute | Interval::Hour => format!(
+                    "{} - {}",
+                    start.format("%F (%A), %H:%M:%S"),
+                    end.format("%F (%A), %H:%M:%S (%Z)")
+                ),
+                _ => {
+                    let last_day = end.date_naive().pred_opt().expect("Date out of range");
+                    format!(
+                        "{} - {}",
+                        start.format("%F (%A)"),
+                        last_day.format("%F (%A)")
+                    )
+                }
+            };
+        }
+
+        match self.1.interval {
+            Interval::Second => date.format("%F (%A), %H:%M:%S (%Z)"),
             Interval::Minute => {
                 let next_date: Timestamp = date + Duration::one_minute();
 
@@ -58,20 +104,96 @@ impl RecordBucket {
                 format!("{}{}", fst, snd)
             }
             Interval::Day => date.format("%F (%A)"),
-            Interval::Week => date.format("CW %U (%B %Y)"),
+            Interval::Week => {
+                let (year, week) = self.2.week_of(date.date_naive());
+                let first_day = self.2.start_of_week(date.date_naive());
+                let last_day = first_day + chrono::Duration::days(6);
+
+                format!("CW {}/{} ({} - {})", week, year, first_day, last_day)
+            }
             Interval::Month => date.format("%B %Y"),
+            Interval::Quarter => {
+                let (year, quarter) = self.2.quarter_of(date.date_naive());
+                let first_day = date.start_of_interval(Interval::Quarter, 0, &self.2);
+                let last_month = first_day.start_of_interval(Interval::Month, 2, &self.2);
+
+                // Quarters of fiscal years that span two calendar years are named like them
+                match self.2.fiscal_year_start {
+                    1 => format!(
+                        "Q{} {} ({} - {})",
+                        quarter,
+                        year,
+                        first_day.format("%B"),
+                        last_month.format("%B")
+                    ),
+                    _ => format!(
+                        "Q{} FY {}/{:02} ({} - {})",
+                        quarter,
+                        year,
+                        (year + 1) % 100,
+                        first_day.format("%B %Y"),
+                        last_month.format("%B %Y")
+                    ),
+                }
+            }
             Interval::Year => date.format("%Y"),
+            Interval::FiscalYear => {
+                let year = self.2.fiscal_year_of(date.date_naive());
+                let first_day = self.2.start_of_fiscal_year(year);
+                let last_day = self.2.start_of_fiscal_year(year + 1).pred_opt().unwrap();
+
+                // Fiscal years that span two calendar years are named after both
+                match self.2.fiscal_year_start {
+                    1 => format!("FY {}", year),
+                    _ => format!(
+                        "FY {}/{:02} ({} - {})",
+                        year,
+                        (year + 1) % 100,
+                        first_day.format("%B %Y"),
+                        last_day.format("%B %Y")
+                    ),
+                }
+            }
         }
     }
     fn size(&self) -> usize {
         self.0.len()
     }
     fn rounded_duration_sum(&self, opt: &RecordFormattingOptions) -> Duration {
-        self.duration_sum().round(&opt.rounding_opts)
+        self.0
+            .iter()
+            .map(|r| r.duration().round(r.rounding_opts(opt)))
+            .sum::<Duration>()
     }
     fn duration_sum(&self) -> Duration {
         self.0.iter().map(|r| r.duration()).sum::<Duration>()
     }
+    /// Sums the durations of all records with a tag, for each tag
+    fn duration_sum_by_tag(&self) -> BTreeMap<String, Duration> {
+        let mut records_by_tag = BTreeMap::new();
+
+        for r in self.0.iter() {
+            // The project of a record is summed along with its amount instead
+            let project = r.project.as_ref().map(|p| format!("+{}", p).to_lowercase());
+
+            // A tag that occurs twice in a note still counts the record once
+            let tags = r
+                .tags()
+                .into_iter()
+                .map(|t| t.to_lowercase())
+                .filter(|t| Some(t) != project.as_ref())
+                .collect::<BTreeSet<_>>();
+
+            for tag in tags {
+                records_by_tag.entry(tag).or_insert_with(Vec::new).push(r);
+            }
+        }
+
+        records_by_tag
+            .into_iter()
+            .map(|(tag, records)| (tag, records.iter().map(|r| r.duration()).sum()))
+            .collect()
+    }
     fn duration_avg(&self) -> Duration {
         Mean::mean(self.0.iter().map(|r| r.duration()))
     }
@@ -79,17 +201,42 @@ impl RecordBucket {
         let records = (self.0)
             .iter()
             .rev()
-            .map(|r| r.format_with(&opts))
+            .map(|r| r.format_with(opts))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let stats = std::iter::once(self.format_stats_with(opts))
+            .chain(self.format_tags_with(opts))
+            .chain(self.format_projects_with(opts))
             .collect::<Vec<_>>()
             .join("\n");
 
         println!(
             "{}\n{}\n{}\n",
-            self.name().bold().underline().to_string(),
-            self.format_stats_with(&opts),
+            self.name().bold().underline(),
+            stats,
             records
         )
     }
+    pub fn summarize(&self, key: u32, opt: &RecordFormattingOptions) -> BucketSummary<'_> {
+        BucketSummary {
+            name: self.name(),
+            interval: self.1.to_string(),
+            key,
+            count: self.size(),
+            sum: self.duration_sum().in_seconds(),
+            rounded_sum: self.duration_sum().round(&opt.rounding_opts).in_seconds(),
+            sum_of_rounded: self.rounded_duration_sum(opt).in_seconds(),
+            avg: self.duration_avg().in_seconds(),
+            // Records are kept newest first
+            records: self
+                .0
+                .iter()
+                .rev()
+                .map(|r| ExportedRecord::new(r, r.rounding_opts(opt)))
+                .collect(),
+        }
+    }
     pub fn format_stats_with(&self, opt: &RecordFormattingOptions) -> String {
         let num_punches = self.size().to_string().bright_green();
 
@@ -97,7 +244,7 @@ impl RecordBucket {
         let avg = self.duration_avg();
 
         let rounded_sum = sum.round(&opt.rounding_opts);
-        let sum_of_rounded = self.rounded_duration_sum(&opt);
+        let sum_of_rounded = self.rounded_duration_sum(opt);
 
         format!(
             "{} ⏺️  - sum: {}, rounded sum: {}, sum of rounded: {}, avg: {}",
@@ -108,4 +255,62 @@ impl RecordBucket {
             avg.format(&opt.rounding_opts).bright_green(),
         )
     }
+    /// Formats the sum of each tag, if any record has a tag
+    pub fn format_tags_with(&self, opt: &RecordFormattingOptions) -> Option<String> {
+        let sums = self.duration_sum_by_tag();
+        if sums.is_empty() {
+            return None;
+        }
+
+        let sums = sums
+            .iter()
+            .map(|(tag, sum)| format!("{}: {}", tag, sum.format(&opt.rounding_opts).bright_green()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Some(format!("🏷️  - {}", sums))
+    }
+    /// Formats the sum of each project along with the amount to bill, if any record
+    /// has a project. Records are rounded by the rounding of their project.
+    pub fn format_projects_with(&self, opt: &RecordFormattingOptions) -> Option<String> {
+        let mut records_by_project = BTreeMap::new();
+        for r in self.0.iter() {
+            if let Some(name) = &r.project {
+                records_by_project
+                    .entry(name.as_str())
+                    .or_insert_with(Vec::new)
+                    .push(r);
+            }
+        }
+        if records_by_project.is_empty() {
+            return None;
+        }
+
+        let sums = records_by_project
+            .into_iter()
+            .map(|(name, records)| {
+                let project = opt.projects.get(name);
+                let rounding_opts =
+                    project.map_or(&opt.rounding_opts, |p| p.rounding(&opt.rounding_opts));
+                let sum = records
+                    .iter()
+                    .map(|r| r.duration().round(rounding_opts))
+                    .sum::<Duration>();
+
+                let mut formatted = match project.and_then(|p| p.client.as_ref()) {
+                    Some(client) => format!("{} ({})", name, client),
+                    None => name.to_string(),
+                };
+                formatted.push_str(&format!(": {}", sum.format(rounding_opts).bright_green()));
+                if let Some(amount) = project.and_then(|p| p.format_amount(&sum)) {
+                    formatted.push_str(&format!(", {}", amount.bright_yellow()));
+                }
+
+                formatted
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        Some(format!("💼 - {}", sums))
+    }
 }
@@ -1,23 +1,32 @@
 use std::collections::BTreeMap;
-use std::fs::{self, File, OpenOptions};
-use std::path::PathBuf;
+use std::ffi::OsString;
 use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 
-use ::csv::{Reader, ReaderBuilder, Writer, WriterBuilder};
 use colored::*;
-use dirs;
 
 use crate::bucket::RecordBucket;
+use crate::doctor::{self, Problem};
 use crate::err::*;
-use crate::format::CardFormattingOptions;
+use crate::file::FileLock;
+use crate::format::{CardFormattingOptions, ShowFormat};
+use crate::import::{self, Merge};
+use crate::journal::{Change, Journal, Operation};
 use crate::record::Record;
+use crate::store::{csv, Backend, CardStore};
 use crate::time::Timestamp;
 
-const CARD_EXT: &str = "csv";
 const CARD_NAME_DEFAULT: &str = "main";
 const CARD_DIR: &str = ".punch";
+const BACKUP_EXT: &str = "bak";
+const LOCK_EXT: &str = "lock";
+const ACTIVE_CARD_FILE: &str = "active";
+// Used to print timestamps in error messages
+const TIME_FORMAT: &str = "%F %T %Z";
 
-pub struct Card(PathBuf);
+pub struct Card(PathBuf, Box<dyn CardStore>);
 
 #[derive(Debug)]
 pub enum CardStatus {
@@ -33,125 +42,480 @@ impl fmt::Display for CardStatus {
 }
 
 impl Card {
-    fn new(path: PathBuf) -> Result<Self> {
-        // Assumes that the directory already exists
-        OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&path)
-            .chain_err(|| "Failed to create card file")?;
+    /// Opens the card file with the store matching its extension, creating it if necessary.
+    /// Cards written by older versions are backed up and migrated right away.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let backend = Backend::of(&path).expect("Card file has no backend extension");
+        let card = Card(path.clone(), backend.open(path)?);
+
+        if card.1.is_outdated()? {
+            let _lock = card.lock()?;
+            let backup = card.backup()?;
+            if let Err(e) = card.1.migrate() {
+                // The card is left untouched, so that the next attempt backs it up again
+                let _ = fs::remove_file(&backup);
+                return Err(e).chain_err(|| format!("Failed to migrate card {}", card.name()));
+            }
+        }
+
+        Ok(card)
+    }
+
+    /// Returns the card with the given name, or the active card if no name is given.
+    /// The default card is created on first use, all other cards must be created explicitly.
+    pub fn select(name: Option<&str>) -> Result<Self> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => Card::active_name()?,
+        };
+
+        if let Some(path) = Card::find(&name)? {
+            Card::open(path)
+        } else if name == CARD_NAME_DEFAULT {
+            Card::open(Card::path_of(&name, Backend::default())?)
+        } else {
+            Err(ErrorKind::CardNotFound(name).into())
+        }
+    }
+
+    pub fn create(name: &str, backend: Backend) -> Result<Self> {
+        if Card::find(name)?.is_some() {
+            return Err(ErrorKind::CardAlreadyExists(name.into()).into());
+        }
 
-        Ok(Card(path))
+        Card::open(Card::path_of(name, backend)?)
     }
 
-    #[allow(dead_code)]
-    fn name(&self) -> &str {
+    /// Returns the names of all cards by their files. Cards are not opened, so that a
+    /// card that can't be read doesn't hide the others.
+    pub fn list() -> Result<Vec<String>> {
+        let mut names = fs::read_dir(Card::dir()?)?
+            .filter_map(std::result::Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && Backend::of(path).is_some())
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names.dedup();
+
+        Ok(names)
+    }
+
+    pub fn active_name() -> Result<String> {
+        let path = Card::dir()?.join(ACTIVE_CARD_FILE);
+
+        match fs::read_to_string(&path) {
+            Ok(name) if !name.trim().is_empty() => Ok(name.trim().to_string()),
+            Ok(_) => Ok(CARD_NAME_DEFAULT.to_string()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(CARD_NAME_DEFAULT.to_string()),
+            Err(e) => Err(e).chain_err(|| "Failed to read active card"),
+        }
+    }
+
+    pub fn is_active(&self) -> Result<bool> {
+        Ok(self.name() == Card::active_name()?)
+    }
+
+    pub fn activate(&self) -> Result<()> {
+        let path = Card::dir()?.join(ACTIVE_CARD_FILE);
+
+        fs::write(path, self.name()).chain_err(|| "Failed to set active card")
+    }
+
+    pub fn rename(self, new_name: &str) -> Result<Self> {
+        let _lock = self.lock()?;
+        if Card::find(new_name)?.is_some() {
+            return Err(ErrorKind::CardAlreadyExists(new_name.into()).into());
+        }
+        let new_path = Card::path_of(new_name, self.backend())?;
+
+        let was_active = self.is_active()?;
+        let name = self.name().to_string();
+        let Card(path, store) = self;
+        // Close the store before its file is moved
+        drop(store);
+
+        fs::rename(&path, &new_path).chain_err(|| "Failed to rename card file")?;
+        Journal::open()?.rename_card(&name, new_name)?;
+
+        let card = Card::open(new_path)?;
+        if was_active {
+            card.activate()?;
+        }
+
+        Ok(card)
+    }
+
+    pub fn remove(self, force: bool) -> Result<()> {
+        let _lock = self.lock()?;
+        if self.is_active()? {
+            return Err(ErrorKind::CannotRemoveActiveCard(self.name().into()).into());
+        }
+        if !force && !self.1.load()?.is_empty() {
+            return Err(ErrorKind::CardNotEmpty(self.name().into()).into());
+        }
+
+        fs::remove_file(&self.0).chain_err(|| "Failed to remove card file")?;
+        Journal::open()?.forget_card(self.name())
+    }
+
+    pub fn dir() -> Result<PathBuf> {
+        let dir = dirs::home_dir()
+            .ok_or(ErrorKind::HomeDirNotFound)?
+            .join(CARD_DIR);
+
+        fs::create_dir_all(&dir).chain_err(|| "Could not create directory to store punch cards")?;
+
+        Ok(dir)
+    }
+
+    // Returns the path of an existing card with the given name, whatever its backend
+    fn find(name: &str) -> Result<Option<PathBuf>> {
+        for backend in Backend::all() {
+            let path = Card::path_of(name, backend)?;
+            if path.exists() {
+                return Ok(Some(path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn path_of(name: &str, backend: Backend) -> Result<PathBuf> {
+        let is_valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        if !is_valid {
+            return Err(ErrorKind::InvalidCardName(name.into()).into());
+        }
+
+        let mut path = Card::dir()?.join(name);
+        path.set_extension(backend.extension());
+
+        Ok(path)
+    }
+
+    pub fn name(&self) -> &str {
         self.0
             .file_stem()
             .expect("Could not get card name")
             .to_str()
             .expect("Could not convert card path to name")
     }
-    pub fn path(&self) -> &PathBuf {
-        &self.0
+    pub fn backend(&self) -> Backend {
+        Backend::of(&self.0).expect("Card file has no backend extension")
     }
 
     pub fn status(&self) -> Result<CardStatus> {
-        let mut reader = self.get_reader()?;
+        let records = match self.1.load() {
+            Ok(records) => records,
+            Err(Error(ErrorKind::MalformedRecord(..), _)) => return Ok(CardStatus::Corrupted),
+            Err(e) => return Err(e),
+        };
 
-        let mut records = reader
-            .deserialize()
-            .filter_map(std::result::Result::ok)
-            .collect::<Vec<Record>>();
+        // Records are returned newest first, so only the first one may be ongoing
+        let last = records.first();
 
-        let last = records.pop();
-
-        if !records.iter().all(|r| r.is_terminated()) {
+        if !records.iter().skip(1).all(|r| r.is_terminated()) {
             Ok(CardStatus::Corrupted)
-        } else if last.is_none() || last.as_ref().unwrap().end.is_some() {
+        } else if last.is_none() || last.unwrap().end.is_some() {
             Ok(CardStatus::PunchedOut)
         } else {
             Ok(CardStatus::PunchedIn)
         }
     }
 
-    pub fn punch_in(&self, timestamp: Timestamp, note: Option<&str>) -> Result<()> {
-        let mut reader = self.get_reader()?;
+    /// Returns the ongoing record, which requires the card to be punched in
+    pub fn ongoing(&self) -> Result<Record> {
+        match self.status()? {
+            CardStatus::PunchedIn => {}
+            CardStatus::PunchedOut => return Err(ErrorKind::IncorrectCardStateForCancel.into()),
+            CardStatus::Corrupted => {
+                return Err(ErrorKind::CardIsCorrupted(self.name().into()).into())
+            }
+        }
 
-        let mut records = reader
-            .deserialize()
-            .filter_map(std::result::Result::ok)
-            .collect::<Vec<Record>>();
+        self.1
+            .load()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| ErrorKind::IncorrectCardStateForCancel.into())
+    }
 
-        // Check if all existing records have an end date
-        if !records.iter().all(|r| r.is_terminated()) {
-            return Err(ErrorKind::IncorrectCardStateForIn.into());
-        }
+    /// Discards the ongoing record
+    pub fn cancel(&self) -> Result<Record> {
+        let lock = self.lock()?;
+        let ongoing = self.ongoing()?;
 
-        records.insert(0, Record::from((
-            timestamp,
-            records.len(),
-            note.map(String::from),
-        )));
+        let records = self.1.load()?.into_iter().skip(1).collect::<Vec<_>>();
 
-        let writer = self.get_writer()?;
-        Card::write_records_to_file(writer, records)
+        let changes = Change::between(std::slice::from_ref(&ongoing), &[]);
+        self.commit(&lock, Operation::Cancel, changes, |store| {
+            store.store(&records)
+        })?;
+
+        Ok(ongoing)
+    }
+
+    pub fn punch_in(
+        &self,
+        timestamp: Timestamp,
+        note: Option<&str>,
+        project: Option<&str>,
+    ) -> Result<()> {
+        let lock = self.lock()?;
+        let last = self.1.last()?;
+
+        let record = Card::start_record(last.as_ref(), timestamp, note, project)?;
+
+        let records = [record];
+        let changes = Change::between(&[], &records);
+        self.commit(&lock, Operation::In, changes, |store| store.write(&records))
     }
 
     pub fn punch_out(&self, timestamp: Timestamp, note: Option<String>) -> Result<()> {
-        let mut reader = self.get_reader()?;
+        let lock = self.lock()?;
+        let last = self.1.last()?;
 
-        let mut records = reader
-            .deserialize()
-            .filter_map(std::result::Result::ok)
-            .collect::<Vec<Record>>();
+        let record = Card::terminate_record(last.clone(), timestamp, note)?;
+
+        let records = [record];
+        let changes = Change::between(last.as_slice(), &records);
+        self.commit(&lock, Operation::Out, changes, |store| {
+            store.write(&records)
+        })
+    }
 
-        // Check that all 1..n records have an end date
-        // and that the first record can be terminated.
-        if !records.iter().skip(1).all(|r| r.is_terminated())
-            || records.first().is_none()
-            || records.first().as_ref().unwrap().end.is_some()
-        {
-            return Err(ErrorKind::IncorrectCardStateForOut.into());
+    /// Terminates the ongoing record and starts a new one at the same instant
+    pub fn switch(
+        &self,
+        timestamp: Timestamp,
+        note: Option<&str>,
+        project: Option<&str>,
+    ) -> Result<()> {
+        let lock = self.lock()?;
+        let last = self.1.last()?;
+
+        let terminated = Card::terminate_record(last.clone(), timestamp, None)?;
+        let started = Card::start_record(Some(&terminated), timestamp, note, project)?;
+
+        // Both records are written at once so that the switch can't be interrupted halfway
+        let records = [started, terminated];
+        let changes = Change::between(last.as_slice(), &records);
+        self.commit(&lock, Operation::Switch, changes, |store| {
+            store.write(&records)
+        })
+    }
+
+    // Starts a record after the newest one, which must be terminated. Only the newest
+    // record is read, so that punches don't depend on the size of the card.
+    fn start_record(
+        last: Option<&Record>,
+        timestamp: Timestamp,
+        note: Option<&str>,
+        project: Option<&str>,
+    ) -> Result<Record> {
+        if last.is_some_and(|r| !r.is_terminated()) {
+            return Err(ErrorKind::IncorrectCardStateForIn.into());
         }
+        Card::check_not_in_future(timestamp)?;
+        if let Some(previous_end) = last.and_then(|r| r.end) {
+            if timestamp < previous_end {
+                return Err(
+                    ErrorKind::PunchInBeforeLastPunchOut(previous_end.format(TIME_FORMAT)).into(),
+                );
+            }
+        }
+
+        // Indices are chronological
+        let i = last.map_or(0, |r| r.i + 1);
+        let mut record = Record::from((timestamp, i, note.map(String::from)));
+        record.project = project.map(String::from);
+
+        Ok(record)
+    }
 
-        let first = records.first_mut().unwrap();
-        first.end.replace(timestamp);
+    // Terminates the newest record, which must be ongoing
+    fn terminate_record(
+        last: Option<Record>,
+        timestamp: Timestamp,
+        note: Option<String>,
+    ) -> Result<Record> {
+        let mut record = match last {
+            Some(r) if r.end.is_none() => r,
+            _ => return Err(ErrorKind::IncorrectCardStateForOut.into()),
+        };
+
+        Card::check_not_in_future(timestamp)?;
+        if timestamp < record.start {
+            return Err(ErrorKind::PunchOutBeforePunchIn(record.start.format(TIME_FORMAT)).into());
+        }
+        record.end.replace(timestamp);
 
         if let Some(snd) = note {
-            let new_note = first
+            let new_note = record
                 .note
                 .as_ref()
                 .map_or(snd.clone(), |fst| format!("{};{}", fst, snd));
-            first.note.replace(new_note);
+            record.note.replace(new_note);
         }
 
-        let writer = self.get_writer()?;
-        Card::write_records_to_file(writer, records)
+        Ok(record)
     }
 
-    pub fn display_with(&self, mut opts: CardFormattingOptions) -> Result<()> {
-        let mut reader = self.get_reader()?;
+    pub fn add(
+        &self,
+        start: Timestamp,
+        end: Timestamp,
+        note: Option<&str>,
+        project: Option<&str>,
+    ) -> Result<Record> {
+        let lock = self.lock()?;
+        let before = self.1.load()?;
+        let mut records = before.clone();
+
+        if end < start {
+            return Err(ErrorKind::RecordEndsBeforeStart.into());
+        }
+        Card::check_not_in_future(end)?;
+
+        if let Some(r) = Record::find_overlap(&records, start, end) {
+            return Err(ErrorKind::RecordOverlaps(r.i).into());
+        }
+
+        // Records are returned newest first
+        let position = records
+            .iter()
+            .position(|r| r.start < start)
+            .unwrap_or(records.len());
+
+        let record = Record {
+            i: 0,
+            start,
+            end: Some(end),
+            note: note.map(String::from),
+            project: project.map(String::from),
+        };
+        records.insert(position, record);
+        // All newer records move up by one
+        Record::index_chronologically(&mut records);
+
+        let added = records[position].clone();
+
+        let changes = Change::between(&before, &records);
+        self.commit(&lock, Operation::Add, changes, |store| {
+            store.store(&records)
+        })?;
+
+        Ok(added)
+    }
+
+    /// Merges imported records into the card and skips those that conflict with existing
+    /// records. Nothing is written in a dry run.
+    pub fn import(&self, imported: Vec<Record>, dry_run: bool) -> Result<Merge> {
+        let lock = self.lock()?;
+        let existing = self.1.load()?;
+        let merge = import::merge(existing.clone(), imported);
+
+        if !dry_run && !merge.added.is_empty() {
+            let changes = Change::between(&existing, &merge.records);
+            self.commit(&lock, Operation::Import, changes, |store| {
+                store.store(&merge.records)
+            })?;
+        }
+
+        Ok(merge)
+    }
+
+    /// Returns the records that overlap with the range, newest first
+    pub fn query(&self, since: Option<Timestamp>, until: Option<Timestamp>) -> Result<Vec<Record>> {
+        self.1.query(since, until)
+    }
+
+    /// Lists all problems of the card, including rows that can't be parsed
+    pub fn diagnose(&self) -> Result<Vec<Problem>> {
+        Ok(doctor::diagnose(&doctor::parse(&self.contents()?)))
+    }
+
+    /// Backs up the card and rewrites it without problems. Returns the path of the backup.
+    pub fn repair(&self) -> Result<PathBuf> {
+        let lock = self.lock()?;
+        let backup = self.backup()?;
+
+        let before = self.readable_records()?;
+        let records = doctor::repair(doctor::parse(&self.contents()?));
+        let changes = Change::between(&before, &records);
+        self.commit(&lock, Operation::Repair, changes, |store| {
+            store.store(&records)
+        })?;
+
+        Ok(backup)
+    }
+
+    /// Copies the card file next to it with a timestamp in its name
+    pub fn backup(&self) -> Result<PathBuf> {
+        let mut path = self.0.clone();
+        path.set_extension(format!(
+            "{}.{}",
+            Timestamp::now().format("%Y%m%dT%H%M%S"),
+            BACKUP_EXT
+        ));
 
+        fs::copy(&self.0, &path).chain_err(|| "Failed to back up card file")?;
+
+        Ok(path)
+    }
+
+    pub fn display_with(&self, mut opts: CardFormattingOptions) -> Result<()> {
         let mut num_total_records = 0;
-        let bucket_map = reader
-            .deserialize()
-            .filter_map(std::result::Result::ok)
+        let bucket_map = self
+            .1
+            .query(opts.since, opts.until)?
+            .into_iter()
+            .map(|r| r.in_zone(opts.calendar.zone))
+            .filter(|r| opts.tag.as_ref().is_none_or(|tag| r.has_tag(tag)))
+            .flat_map(|r| match opts.split {
+                true => r.split_at_boundaries(opts.span, &opts.calendar),
+                false => vec![r],
+            })
+            // Parts of split records can lie outside of the range
+            .filter(|r| r.overlaps(opts.since, opts.until))
             .fold(BTreeMap::new(), |mut acc, record: Record| {
                 num_total_records += 1;
-                let key = record.bucket_key(opts.interval);
+                let key = record.bucket_key(opts.span, &opts.calendar);
 
                 #[allow(clippy::or_fun_call)]
                 acc.entry(key)
-                    .or_insert(RecordBucket::new(opts.interval, opts.record_opts.precise))
+                    .or_insert(RecordBucket::new(
+                        opts.span,
+                        opts.calendar,
+                        opts.record_opts.precise,
+                    ))
                     .add(record);
 
                 acc
             });
 
+        if let ShowFormat::Json = opts.format {
+            let summaries = bucket_map
+                .iter()
+                .map(|(key, bucket)| bucket.summarize(*key, &opts.record_opts))
+                .collect::<Vec<_>>();
+            let json = serde_json::to_string_pretty(&summaries)
+                .chain_err(|| "Could not serialize records")?;
+            println!("{}", json);
+
+            return Ok(());
+        }
+
         println!("Showing card {}\n", self.name().bold());
 
         match num_total_records {
+            0 if opts.since.is_some() || opts.until.is_some() => {
+                println!("{}\n", "no punches in this range".italic().dimmed())
+            }
             0 => println!("{}\n", "no punches yet".italic().dimmed()),
             n => {
                 opts.record_opts.align_with_n_records = n;
@@ -165,42 +529,100 @@ impl Card {
         Ok(())
     }
 
-    fn get_reader(&self) -> Result<Reader<std::fs::File>> {
-        ReaderBuilder::new()
-            .has_headers(false)
-            .flexible(true)
-            .from_path(&self.0)
-            .chain_err(|| "Could not initialize reader")
+    fn check_not_in_future(timestamp: Timestamp) -> Result<()> {
+        if timestamp > Timestamp::now() {
+            return Err(ErrorKind::PunchInFuture(timestamp.format(TIME_FORMAT)).into());
+        }
+
+        Ok(())
     }
 
-    fn get_writer(&self) -> Result<Writer<std::fs::File>> {
-        WriterBuilder::new()
-            .flexible(true)
-            .has_headers(false)
-            .from_path(&self.0)
-            .chain_err(|| "Could not initialize writer")
+    /// Returns the card in CSV format, whatever its backend
+    pub fn contents(&self) -> Result<String> {
+        self.1.snapshot()
     }
 
-    fn write_records_to_file(mut writer: Writer<File>, records: Vec<Record>) -> Result<()> {
-        for r in records {
-            writer.serialize(r)?;
+    /// Returns the records in CSV format with one row per record, dropping rows that
+    /// were superseded by punching out. Cards that can't be parsed are returned as they
+    /// are, so that they can be fixed by hand.
+    pub fn editable_contents(&self) -> Result<String> {
+        match self.1.load() {
+            Ok(records) => csv::serialize(&records),
+            Err(Error(ErrorKind::MalformedRecord(..), _)) => self.contents(),
+            Err(e) => Err(e),
         }
+    }
 
-        writer.flush().chain_err(|| "Could not write to card file")
+    /// Locks the card for a read-modify-write. Other punch processes wait for the lock
+    /// until it is released when dropped, or time out.
+    pub fn lock(&self) -> Result<FileLock> {
+        let mut name = OsString::from(".");
+        name.push(self.0.file_stem().expect("Could not get card name"));
+        name.push(".");
+        name.push(LOCK_EXT);
+
+        FileLock::acquire(&self.0.with_file_name(name))
     }
-}
 
-impl Default for Card {
-    fn default() -> Self {
-        let home_dir = dirs::home_dir().expect("Failed to get home dir");
+    /// Replaces the card with contents in CSV format and journals the operation so that it can be undone
+    pub fn replace(&self, lock: &FileLock, op: Operation, contents: String) -> Result<()> {
+        let changes = Change::between(&self.readable_records()?, &csv::deserialize(&contents)?);
 
-        let dir = home_dir.join(CARD_DIR);
+        self.commit(lock, op, changes, |store| store.restore(&contents))
+    }
 
-        fs::create_dir_all(&dir).expect("Could not create directory to store punch cards");
+    /// Applies journaled changes without journaling them again. Fails if a changed record
+    /// was modified since the operation, e.g. by hand.
+    pub fn apply(&self, _lock: &FileLock, op: Operation, changes: &[Change]) -> Result<()> {
+        let mut records = self
+            .1
+            .load()?
+            .into_iter()
+            .map(|r| (r.i, r))
+            .collect::<BTreeMap<_, _>>();
+
+        for change in changes {
+            if records.get(&change.i) != change.before.as_ref() {
+                return Err(ErrorKind::CardModifiedSinceOperation(op.to_string()).into());
+            }
+            match &change.after {
+                Some(r) => records.insert(change.i, r.clone()),
+                None => records.remove(&change.i),
+            };
+        }
+
+        // Indices are chronological, so ordering by index orders the records newest first
+        self.1
+            .store(&records.into_values().rev().collect::<Vec<_>>())
+    }
 
-        let mut card_path = dir.join(CARD_NAME_DEFAULT);
-        card_path.set_extension(CARD_EXT);
+    // Modifies the card and journals the changed records so that the operation can be undone
+    fn commit<F>(
+        &self,
+        _lock: &FileLock,
+        op: Operation,
+        changes: Vec<Change>,
+        modify: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&dyn CardStore) -> Result<()>,
+    {
+        modify(self.1.as_ref())?;
+
+        Journal::open()?.record(self.name(), op, changes)
+    }
 
-        Card::new(card_path).expect("Failed to create default punch card")
+    // Returns all records that can be parsed, so that changes to broken cards can be
+    // journaled as well
+    fn readable_records(&self) -> Result<Vec<Record>> {
+        match self.1.load() {
+            Err(Error(ErrorKind::MalformedRecord(..), _)) => {
+                let rows = doctor::parse(&self.contents()?);
+                let records = rows.into_iter().filter_map(|row| row.record.ok()).collect();
+
+                Ok(doctor::supersede(records, |r| r))
+            }
+            records => records,
+        }
     }
 }
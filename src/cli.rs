@@ -1,6 +1,12 @@
+use crate::export::ExportFormat;
+use crate::format::ShowFormat;
+use crate::import::ImportFormat;
+use crate::invoice::InvoiceFormat;
 use crate::round::RoundingOptions;
-use crate::time::Interval;
+use crate::store::Backend;
+use crate::time::{Interval, Period, Span, Zone};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::convert::TryFrom;
 
 pub fn get_matches<'a>() -> ArgMatches<'a> {
     let arg_note = Arg::with_name("note")
@@ -9,9 +15,50 @@ pub fn get_matches<'a>() -> ArgMatches<'a> {
         .empty_values(false)
         .index(1);
 
+    let arg_card = Arg::with_name("card")
+        .help("The punch card to use instead of the active one")
+        .long("card")
+        .short("c")
+        .takes_value(true)
+        .value_name("NAME");
+
+    let arg_at = Arg::with_name("at")
+        .help("Punch at the given time instead of now, e.g. \"09:15\", \"yesterday 17:30\" or an RFC 3339 timestamp")
+        .long("at")
+        .takes_value(true)
+        .value_name("TIME")
+        .conflicts_with("ago");
+
+    let arg_ago = Arg::with_name("ago")
+        .help("Punch the given duration ago instead of now, e.g. \"15min\" or \"1h30m\"")
+        .long("ago")
+        .takes_value(true)
+        .value_name("DURATION");
+
+    let arg_project = Arg::with_name("project")
+        .help("Bill the record to a project from ~/.punch/projects.toml")
+        .long("project")
+        .short("p")
+        .takes_value(true)
+        .value_name("PROJECT");
+
+    // A default value of "nearest,1min" is implemented through the Default trait instead of clap::Arg::default_value
+    let arg_rounding = Arg::with_name("rounding")
+        .long("round")
+        .short("r")
+        .takes_value(true)
+        .value_name("DIRECTION,GRANULARITY")
+        .validator(RoundingOptions::validate_str)
+        .help("Rounding string in format <DIRECTION,GRANULARITY> to specify rounding options for time durations. For example: nearest,1min (default); up,5min; down,1day");
+
+    let arg_card_name = Arg::with_name("name")
+        .help("Name of the punch card")
+        .required(true)
+        .index(1);
+
     #[allow(deprecated)]
     App::new("punch")
-        .author(crate_authors!("\n"))
+        .author(env!("CARGO_PKG_AUTHORS"))
         .version(crate_version!())
         .version_short("v")
         .settings(&[
@@ -20,31 +67,269 @@ pub fn get_matches<'a>() -> ArgMatches<'a> {
         ])
         .subcommand(
             SubCommand::with_name("status")
-                .about("Show punch status"),
+                .about("Show punch status")
+                .arg(&arg_card),
         )
         .subcommand(
             SubCommand::with_name("edit")
-                .about("Edit a punch card"),
+                .about("Edit a punch card")
+                .arg(&arg_card),
         )
         .subcommand(
             SubCommand::with_name("in")
                 .about("Punch in - start tracking time")
-                .arg(&arg_note),
+                .arg(&arg_note)
+                .arg(&arg_at)
+                .arg(&arg_ago)
+                .arg(&arg_project)
+                .arg(&arg_card),
         )
         .subcommand(
             SubCommand::with_name("out")
                 .about("Punch out - stop tracking time")
-                .arg(&arg_note),
+                .arg(&arg_note)
+                .arg(&arg_at)
+                .arg(&arg_ago)
+                .arg(&arg_card),
+        )
+        .subcommand(
+            SubCommand::with_name("switch")
+                .about("Punch out and punch in again at the same time - switch to another task")
+                .arg(arg_note.clone().help("Attach a note to the new record"))
+                .arg(&arg_at)
+                .arg(&arg_ago)
+                .arg(&arg_project)
+                .arg(&arg_card),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel")
+                .about("Discard the ongoing record")
+                .arg(&arg_card)
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .short("y")
+                        .help("Don't ask for confirmation"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Add a completed record retroactively")
+                .arg(
+                    Arg::with_name("start")
+                        .help("Start of the record, e.g. \"09:15\", \"yesterday 17:30\" or an RFC 3339 timestamp")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("end")
+                        .help("End of the record, in the same format as <start>")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(arg_note.clone().index(3))
+                .arg(&arg_project)
+                .arg(&arg_card),
+        )
+        .subcommand(
+            SubCommand::with_name("card")
+                .about("Manage punch cards")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .alias("ls")
+                        .about("List all punch cards and their status"),
+                )
+                .subcommand(
+                    SubCommand::with_name("new")
+                        .about("Create a new punch card")
+                        .arg(&arg_card_name)
+                        .arg(
+                            Arg::with_name("backend")
+                                .long("backend")
+                                .short("b")
+                                .takes_value(true)
+                                .case_insensitive(true)
+                                .possible_values(&Backend::variants())
+                                .default_value("csv")
+                                .help("How the card is stored, SQLite cards are faster to query"),
+                        )
+                        .arg(
+                            Arg::with_name("switch")
+                                .long("switch")
+                                .short("s")
+                                .help("Make the new card the active one"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("switch")
+                        .about("Make a punch card the active one")
+                        .arg(&arg_card_name),
+                )
+                .subcommand(
+                    SubCommand::with_name("rename")
+                        .about("Rename a punch card")
+                        .arg(&arg_card_name)
+                        .arg(
+                            Arg::with_name("new_name")
+                                .help("New name of the punch card")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rm")
+                        .alias("remove")
+                        .about("Remove a punch card")
+                        .arg(&arg_card_name)
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .short("f")
+                                .help("Remove the card even if it contains records"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Diagnose and repair a corrupted punch card")
+                .arg(&arg_card)
+                .arg(
+                    Arg::with_name("fix")
+                        .long("fix")
+                        .help("Repair the card after backing it up: drop broken rows, trim overlaps, reorder and renumber records"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("Undo the last operation on a punch card")
+                .arg(&arg_card),
+        )
+        .subcommand(
+            SubCommand::with_name("redo")
+                .about("Redo the last undone operation on a punch card")
+                .arg(&arg_card),
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("List recent operations on a punch card")
+                .arg(&arg_card)
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .short("n")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("Maximum number of operations to list"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Print records in a format for other programs")
+                .arg(&arg_card)
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .short("f")
+                        .takes_value(true)
+                        .case_insensitive(true)
+                        .possible_values(&ExportFormat::variants())
+                        .default_value("json")
+                        .help("The output format, NDJSON prints one JSON object per line"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .value_name("TIME")
+                        .help("Only export records that end after this time, e.g. \"2020-02-01\", \"yesterday 17:30\", \"-2w\" or an RFC 3339 timestamp"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .value_name("TIME")
+                        .help("Only export records that start before this time, in the same format as --since"),
+                )
+                .arg(arg_rounding.clone().help("Rounding of the rounded durations, in the same format as for `punch show`"))
+                .arg(
+                    Arg::with_name("ongoing")
+                        .long("ongoing")
+                        .help("Export the ongoing record as if it ended now. Otherwise it has no end, and is left out of iCalendar files"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Add records from the export of another time tracker")
+                .arg(&arg_card)
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .required(true)
+                        .case_insensitive(true)
+                        .possible_values(&ImportFormat::variants())
+                        .help("The time tracker that exported the file: a Toggl CSV report, `timew export`, `watson log --json` or Watson's frames file, or an iCalendar file"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .index(1)
+                        .required(true)
+                        .value_name("FILE")
+                        .help("The file to import"),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .short("n")
+                        .help("Only report which records would be added"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("invoice")
+                .about("Print a timesheet of the records billed to a client in a month")
+                .arg(
+                    Arg::with_name("client")
+                        .long("client")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("CLIENT")
+                        .help("The client as configured in ~/.punch/projects.toml"),
+                )
+                .arg(
+                    Arg::with_name("month")
+                        .long("month")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("YYYY-MM")
+                        .help("The month to bill, e.g. 2020-02"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .short("f")
+                        .takes_value(true)
+                        .case_insensitive(true)
+                        .possible_values(&InvoiceFormat::variants())
+                        .default_value("markdown")
+                        .help("The output format"),
+                )
+                .arg(arg_rounding.clone().help("Rounding of records whose project has no rounding, in the same format as for `punch show`")),
         )
         .subcommand(
             SubCommand::with_name("show")
                 .about("Show details of a punch card")
+                .arg(&arg_card)
                 .arg(
                     Arg::with_name("interval")
-                        .help("The time interval at which records should be grouped together")
+                        .help("The time interval at which records should be grouped together, optionally preceded by a number of units like \"2week\". Or a range to show like \"today\" or \"last-month\", which groups records by day, by week for months and by month for longer ranges")
                         .index(1)
-                        .case_insensitive(true)
-                        .possible_values(&Interval::variants())
+                        .validator(|value| match Period::try_from(value.as_str()).is_ok() || Span::try_from(value.as_str()).is_ok() {
+                            true => Ok(()),
+                            false => Err(format!("expected one of {}, or one of {}", Interval::variants().join(", "), Period::NAMES.join(", "))),
+                        })
                         // IMPROVE: passing Interval::Week here is favorable but clap requires a &str
                         .default_value("week"),
                 )
@@ -60,14 +345,82 @@ pub fn get_matches<'a>() -> ArgMatches<'a> {
                         .help("Print timestamps with timezones"),
                 )
                 .arg(
-                    // A default value of "nearest,1min" is implemented through the Default trait instead of clap::Arg::default_value
-                    Arg::with_name("rounding")
-                        .long("round")
-                        .short("r")
+                    Arg::with_name("tz")
+                        .long("tz")
+                        .takes_value(true)
+                        .value_name("ZONE")
+                        .validator(|v| {
+                            Zone::try_from(v.as_str()).map(|_| ()).map_err(|e| e.to_string())
+                        })
+                        .help(
+                            "Show and group records in this IANA timezone, e.g. \"Europe/Berlin\", \
+                             or \"local\" [default: home timezone of the card, or local]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("recorded_offset")
+                        .long("recorded-offset")
+                        .conflicts_with("tz")
+                        .help("Show and group each record in the UTC offset it was punched with"),
+                )
+                .arg(&arg_rounding)
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .short("f")
+                        .takes_value(true)
+                        .case_insensitive(true)
+                        .possible_values(&ShowFormat::variants())
+                        .default_value("text")
+                        .help("The output format, JSON includes the stats of each group along with its records"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .short("g")
                         .takes_value(true)
-                        .value_name("DIRECTION,GRANULARITY")
-                        .validator(RoundingOptions::validate_str)
-                        .help("Rounding string in format <DIRECTION,GRANULARITY> to specify rounding options for time durations. For example: nearest,1min (default); up,5min; down,1day")
+                        .value_name("TAG")
+                        .help("Only show records whose note contains the tag, e.g. #meeting or +acme. Without # or +, both match"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .value_name("TIME")
+                        .help("Only show records that end after this time, e.g. \"2020-02-01\", \"monday\", \"-2w\" or an RFC 3339 timestamp"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .value_name("TIME")
+                        .help("Only show records that start before this time, in the same format as --since"),
+                )
+                .arg(
+                    Arg::with_name("last")
+                        .long("last")
+                        .short("l")
+                        .takes_value(true)
+                        .value_name("N")
+                        .validator(|v| match v.parse::<i32>() {
+                            Ok(n) if n >= 1 => Ok(()),
+                            _ => Err("expected a number of groups of at least 1".into()),
+                        })
+                        .conflicts_with("since")
+                        .help("Only show the last N groups of records, including the current one"),
+                )
+                .arg(
+                    Arg::with_name("split")
+                        .long("split")
+                        .conflicts_with("no_split")
+                        .help("Split records at the boundaries of groups so that each group sums up its part. Default for days, weeks, months and years"),
+                )
+                .arg(
+                    Arg::with_name("no_split")
+                        .long("no-split")
+                        .help("Count records entirely in the group they start in. Default for minutes and hours"),
                 ),
         )
         .get_matches()
@@ -0,0 +1,20 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::project::Projects;
+use crate::time::Timestamp;
+
+pub fn run(
+    card: Card,
+    start: Timestamp,
+    end: Timestamp,
+    note: Option<&str>,
+    project: Option<&str>,
+) -> Result<()> {
+    let project = Projects::load()?.resolve(project, note)?;
+
+    let record = card.add(start, end, note, project.as_deref())?;
+
+    println!("👊 add - {}", record.format_with(&Default::default()));
+
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::prompt;
+use colored::*;
+
+pub fn run(card: Card, yes: bool) -> Result<()> {
+    let ongoing = card.ongoing()?;
+
+    if !yes {
+        println!("{}", ongoing.format_with(&Default::default()));
+        if !prompt::confirm("Discard this ongoing record?")? {
+            println!("{}", "nothing cancelled".italic().dimmed());
+            return Ok(());
+        }
+    }
+
+    let record = card.cancel()?;
+
+    let suffix = format!(
+        "started at {}",
+        record.start.format_with(&Default::default())
+    );
+    println!("👊 cancel - {}", suffix.dimmed());
+
+    Ok(())
+}
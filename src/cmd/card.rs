@@ -0,0 +1,76 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::store::Backend;
+use colored::*;
+
+pub fn list() -> Result<()> {
+    let active = Card::active_name()?;
+    let names = Card::list()?;
+
+    if names.is_empty() {
+        println!("{}", "no cards yet".italic().dimmed());
+    }
+
+    for name in names {
+        // Cards that can't be opened are listed along with the reason
+        let details = Card::select(Some(&name))
+            .and_then(|card| {
+                Ok(format!(
+                    "{} ({})",
+                    card.status()?,
+                    card.backend().extension()
+                ))
+            })
+            .unwrap_or_else(|e| format!("Unreadable: {}", e));
+
+        if name == active {
+            println!("* {} {}", name.bold(), details.dimmed());
+        } else {
+            println!("  {} {}", name, details.dimmed());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn new(name: &str, backend: Backend, switch: bool) -> Result<()> {
+    let card = Card::create(name, backend)?;
+
+    if switch {
+        card.activate()?;
+    }
+
+    print_success("new", card.name());
+
+    Ok(())
+}
+
+pub fn switch(name: &str) -> Result<()> {
+    let card = Card::select(Some(name))?;
+    card.activate()?;
+
+    print_success("switched to", card.name());
+
+    Ok(())
+}
+
+pub fn rename(name: &str, new_name: &str) -> Result<()> {
+    let card = Card::select(Some(name))?.rename(new_name)?;
+
+    print_success("renamed to", card.name());
+
+    Ok(())
+}
+
+pub fn remove(name: &str, force: bool) -> Result<()> {
+    let card = Card::select(Some(name))?;
+    card.remove(force)?;
+
+    print_success("removed", name);
+
+    Ok(())
+}
+
+fn print_success(action: &str, name: &str) {
+    println!("🗂️  {} - {}", action, name.bold());
+}
@@ -0,0 +1,42 @@
+use crate::card::Card;
+use crate::err::*;
+use colored::*;
+
+pub fn run(card: Card, fix: bool) -> Result<()> {
+    let problems = card.diagnose()?;
+
+    if problems.is_empty() {
+        println!(
+            "🩺 {} - {}",
+            card.name().bold(),
+            "no problems found".dimmed()
+        );
+        return Ok(());
+    }
+
+    println!(
+        "🩺 {} - {} problem(s) found\n",
+        card.name().bold(),
+        problems.len()
+    );
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+
+    if !fix {
+        println!(
+            "\n{}",
+            "Run `punch doctor --fix` to repair the card"
+                .italic()
+                .dimmed()
+        );
+        return Err(ErrorKind::CardIsCorrupted(card.name().into()).into());
+    }
+
+    let backup = card.repair()?;
+
+    let suffix = format!("backup at {}", backup.display());
+    println!("\n🩺 repaired - {}", suffix.dimmed());
+
+    Ok(())
+}
@@ -1,20 +1,79 @@
-use std::process::{Command, ExitStatus};
 use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::process::{self, Command};
 
 use crate::card::Card;
+use crate::doctor;
 use crate::err::*;
+use crate::journal::Operation;
+use crate::prompt;
+use colored::*;
 
-pub fn run() -> Result<ExitStatus> {
-    let card: Card = Default::default();
+const EDITS_DIR: &str = "edits";
 
+/// Edits a temporary copy of the card and only replaces the card once the copy is valid
+pub fn run(card: Card) -> Result<()> {
     let env_editor = "EDITOR";
-    match env::var_os(env_editor) {
-        None => Err(ErrorKind::EnvVarNotFound(env_editor.into()).into()),
-        Some(editor) => Command::new(editor)
-            .arg(card.path())
+    let editor = env::var_os(env_editor).ok_or(ErrorKind::EnvVarNotFound(env_editor.into()))?;
+
+    let before = card.editable_contents()?;
+
+    // The copy is always in CSV format, whatever the backend of the card. It is private to
+    // this process, so that concurrent edits don't overwrite each other's copy, and kept
+    // apart from the cards, so that it isn't listed as one.
+    let dir = Card::dir()?.join(EDITS_DIR);
+    fs::create_dir_all(&dir).chain_err(|| "Failed to create temporary copy of card")?;
+    let copy = dir.join(format!("{}.{}.csv", card.name(), process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&copy)
+        .and_then(|mut file| file.write_all(before.as_bytes()))
+        .chain_err(|| "Failed to create temporary copy of card")?;
+
+    let after = loop {
+        let status = Command::new(&editor)
+            .arg(&copy)
             .spawn()
             .chain_err(|| "Failed to open editor")?
             .wait()
-            .chain_err(|| "Editor returned non-zero exit code")
+            .chain_err(|| "Failed to wait for editor")?;
+        if !status.success() {
+            return Err(ErrorKind::EditorFailed.into());
+        }
+
+        let after = fs::read_to_string(&copy).chain_err(|| "Failed to read temporary copy")?;
+
+        let problems = doctor::diagnose(&doctor::parse(&after));
+        if problems.is_empty() {
+            break after;
+        }
+
+        println!("🩺 {} problem(s) found\n", problems.len());
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+        println!();
+
+        if !prompt::confirm("Reopen the editor?")? {
+            return Err(ErrorKind::EditDiscarded(copy.display().to_string()).into());
+        }
+    };
+
+    if after == before {
+        println!("{}", "no changes".italic().dimmed());
+    } else {
+        let lock = card.lock()?;
+        if card.editable_contents()? != before {
+            return Err(ErrorKind::CardModifiedWhileEditing(copy.display().to_string()).into());
+        }
+
+        card.replace(&lock, Operation::Edit, after)?;
+        println!("✏️  edit - {}", card.name().bold());
     }
+
+    fs::remove_file(&copy).chain_err(|| "Failed to remove temporary copy")?;
+
+    Ok(())
 }
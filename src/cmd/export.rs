@@ -0,0 +1,33 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::export::{self, ExportFormat};
+use crate::project::Projects;
+use crate::round::RoundingOptions;
+use crate::time::Timestamp;
+
+pub fn run(
+    card: Card,
+    format: ExportFormat,
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+    projects: Projects,
+    rounding_opts: RoundingOptions,
+    include_ongoing: bool,
+) -> Result<()> {
+    // Records are exported oldest first
+    let mut records = card.query(since, until)?;
+    records.reverse();
+
+    if include_ongoing {
+        for r in records.iter_mut().filter(|r| r.end.is_none()) {
+            r.end = Some(Timestamp::now());
+        }
+    }
+
+    print!(
+        "{}",
+        export::export(card.name(), &records, format, &projects, &rounding_opts)?
+    );
+
+    Ok(())
+}
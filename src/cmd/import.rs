@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::Path;
+
+use crate::card::Card;
+use crate::err::*;
+use crate::import::{self, ImportFormat};
+use crate::project::Projects;
+use colored::*;
+
+pub fn run(card: Card, format: ImportFormat, path: &str, dry_run: bool) -> Result<()> {
+    if !Path::new(path).is_file() {
+        return Err(ErrorKind::FileDoesNotExist(path.into()).into());
+    }
+    let contents = fs::read_to_string(path).chain_err(|| "Failed to read import file")?;
+
+    let records = import::parse(&contents, format, &Projects::load()?)?;
+    let merge = card.import(records, dry_run)?;
+
+    let verb = if dry_run { "would add" } else { "add" };
+    for r in merge.added.iter() {
+        println!("👊 {} - {}", verb, r.format_with(&Default::default()));
+    }
+    for (r, conflict) in merge.skipped.iter() {
+        let note = r.note.as_deref().unwrap_or_default();
+        println!(
+            "⏭️  skip - {} {} {}",
+            r.start.format("%F %T"),
+            note.dimmed(),
+            format!("({})", conflict).yellow()
+        );
+    }
+
+    let summary = if dry_run {
+        format!(
+            "Dry run, {} record(s) would be added, {} skipped",
+            merge.added.len(),
+            merge.skipped.len()
+        )
+    } else {
+        format!(
+            "{} record(s) added, {} skipped",
+            merge.added.len(),
+            merge.skipped.len()
+        )
+    };
+    println!("\n{}", summary.italic().dimmed());
+
+    Ok(())
+}
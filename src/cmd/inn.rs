@@ -1,15 +1,20 @@
 use crate::card::Card;
 use crate::err::*;
+use crate::project::Projects;
 use crate::time::Timestamp;
 use colored::*;
 
-pub fn run(note: Option<&str>) -> Result<()> {
-    let card: Card = Default::default();
+pub fn run(
+    card: Card,
+    timestamp: Timestamp,
+    note: Option<&str>,
+    project: Option<&str>,
+) -> Result<()> {
+    let project = Projects::load()?.resolve(project, note)?;
 
-    let now = Timestamp::now();
-    card.punch_in(now, note)?;
+    card.punch_in(timestamp, note, project.as_deref())?;
 
-    print_success(now.format_with(&Default::default()));
+    print_success(timestamp.format_with(&Default::default()));
 
     Ok(())
 }
@@ -0,0 +1,23 @@
+use crate::err::*;
+use crate::invoice::{Invoice, InvoiceFormat};
+use crate::round::RoundingOptions;
+use crate::time::Timestamp;
+
+pub fn run(
+    client: &str,
+    month: &str,
+    format: InvoiceFormat,
+    rounding_opts: RoundingOptions,
+) -> Result<()> {
+    let (since, until) = Timestamp::bounds_of_month(month)?;
+    let invoice = Invoice::build(client, since, until, &rounding_opts)?;
+
+    // Printed apart from the invoice, which is usually redirected into a file
+    for (name, e) in invoice.skipped_cards.iter() {
+        handle_warning(&format!("card {} is left out of the invoice: {}", name, e));
+    }
+
+    print!("{}", invoice.format(format)?);
+
+    Ok(())
+}
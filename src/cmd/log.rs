@@ -0,0 +1,33 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::journal::Journal;
+use colored::*;
+
+pub fn run(card: Card, limit: usize) -> Result<()> {
+    let journal = Journal::open()?;
+
+    println!("Showing operations on card {}\n", card.name().bold());
+
+    let mut entries = journal.entries(card.name()).take(limit).peekable();
+    if entries.peek().is_none() {
+        println!("{}", "no operations yet".italic().dimmed());
+    }
+
+    for e in entries {
+        let op = format!("{:<4}", e.op.to_string());
+        let op = if e.undone {
+            format!("{} {}", op.strikethrough().dimmed(), "(undone)".dimmed())
+        } else {
+            op.bold().to_string()
+        };
+
+        println!(
+            "{}: {} {}",
+            e.seq.to_string().dimmed(),
+            e.time.format_with(&Default::default()),
+            op
+        );
+    }
+
+    Ok(())
+}
@@ -1,5 +1,16 @@
+pub mod add;
+pub mod cancel;
+pub mod card;
+pub mod doctor;
+pub mod edit;
+pub mod export;
+pub mod import;
 pub mod inn;
+pub mod invoice;
+pub mod log;
 pub mod out;
+pub mod redo;
 pub mod show;
 pub mod status;
-pub mod edit;
+pub mod switch;
+pub mod undo;
@@ -3,13 +3,10 @@ use crate::err::*;
 use crate::time::Timestamp;
 use colored::*;
 
-pub fn run(note: Option<&str>) -> Result<()> {
-    let card: Card = Default::default();
+pub fn run(card: Card, timestamp: Timestamp, note: Option<&str>) -> Result<()> {
+    card.punch_out(timestamp, note.map(String::from))?;
 
-    let now = Timestamp::now();
-    card.punch_out(now, note.map(String::from))?;
-
-    print_success(now.format_with(&Default::default()));
+    print_success(timestamp.format_with(&Default::default()));
 
     Ok(())
 }
@@ -0,0 +1,15 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::journal::Journal;
+use colored::*;
+
+pub fn run(card: Card) -> Result<()> {
+    let lock = card.lock()?;
+    let mut journal = Journal::open()?;
+    let entry = journal.redo(&card, &lock)?;
+
+    let suffix = format!("from {}", entry.time.format_with(&Default::default()));
+    println!("↪️  redo - {} {}", entry.op, suffix.dimmed());
+
+    Ok(())
+}
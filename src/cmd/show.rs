@@ -2,8 +2,6 @@ use crate::card::Card;
 use crate::err::*;
 use crate::format::CardFormattingOptions;
 
-pub fn run(opts: CardFormattingOptions) -> Result<()> {
-    let card: Card = Default::default();
-
+pub fn run(card: Card, opts: CardFormattingOptions) -> Result<()> {
     card.display_with(opts)
 }
@@ -1,9 +1,7 @@
 use crate::card::Card;
 use crate::err::*;
 
-pub fn run() -> Result<()> {
-    let card: Card = Default::default();
-
+pub fn run(card: Card) -> Result<()> {
     println!("{}", card.status()?);
 
     Ok(())
@@ -0,0 +1,25 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::project::Projects;
+use crate::time::Timestamp;
+use colored::*;
+
+pub fn run(
+    card: Card,
+    timestamp: Timestamp,
+    note: Option<&str>,
+    project: Option<&str>,
+) -> Result<()> {
+    let project = Projects::load()?.resolve(project, note)?;
+
+    card.switch(timestamp, note, project.as_deref())?;
+
+    print_success(timestamp.format_with(&Default::default()));
+
+    Ok(())
+}
+
+fn print_success(time: String) {
+    let suffix = format!("at {}", time);
+    println!("👊 switch - {}", suffix.dimmed());
+}
@@ -0,0 +1,25 @@
+use crate::card::Card;
+use crate::err::*;
+use crate::journal::{Journal, Operation};
+use colored::*;
+
+pub fn run(card: Card) -> Result<()> {
+    let lock = card.lock()?;
+    let mut journal = Journal::open()?;
+    let entry = journal.undo(&card, &lock)?;
+
+    let suffix = format!("from {}", entry.time.format_with(&Default::default()));
+    println!("↩️  undo - {} {}", entry.op, suffix.dimmed());
+
+    // The journal only holds records, the rows themselves are kept in the backup
+    if let Operation::Repair = entry.op {
+        println!(
+            "\n{}",
+            "Rows that `doctor --fix` dropped because they couldn't be parsed are not restored, see its backup"
+                .italic()
+                .dimmed()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+
+use serde::Deserialize;
+
+use crate::card::Card;
+use crate::err::*;
+use crate::time::{Calendar, Zone};
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// Settings in `~/.punch/config.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub calendar: Calendar,
+    /// Settings of single cards, in `[cards.<name>]` tables
+    pub cards: BTreeMap<String, CardConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CardConfig {
+    /// IANA name of the timezone that the card is shown in by default
+    pub timezone: Option<String>,
+}
+
+impl Config {
+    /// Reads the settings. Without a config file, all settings are default.
+    pub fn load() -> Result<Self> {
+        let path = Card::dir()?.join(CONFIG_FILE);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).chain_err(|| "Failed to read config"),
+        };
+
+        let invalid = || ErrorKind::InvalidConfig(path.display().to_string());
+        let config: Config = toml::from_str(&contents).chain_err(invalid)?;
+
+        if !(1..=12).contains(&config.calendar.fiscal_year_start) {
+            return Err(invalid().into());
+        }
+
+        for card in config.cards.values() {
+            if let Some(name) = &card.timezone {
+                Zone::try_from(name.as_str())?;
+            }
+        }
+
+        Ok(config)
+    }
+    /// Returns the home timezone of the card, or the local timezone if it has none
+    pub fn zone_of(&self, card: &Card) -> Result<Zone> {
+        match self
+            .cards
+            .get(card.name())
+            .and_then(|c| c.timezone.as_deref())
+        {
+            Some(name) => Zone::try_from(name),
+            None => Ok(Zone::Local),
+        }
+    }
+}
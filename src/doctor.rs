@@ -0,0 +1,566 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use ::csv::StringRecord;
+
+use crate::record::Record;
+use crate::store::csv;
+use crate::time::Timestamp;
+
+/// A row of a card file along with its line number, parsed if possible. The index of
+/// rows that can't be parsed is kept if it can be read on its own.
+pub struct Row {
+    pub line: u64,
+    pub i: Option<usize>,
+    pub record: std::result::Result<Record, String>,
+}
+
+#[derive(Debug)]
+pub enum Problem {
+    Unparseable {
+        line: u64,
+        reason: String,
+    },
+    EndBeforeStart {
+        line: u64,
+        i: usize,
+    },
+    Unterminated {
+        line: u64,
+        i: usize,
+    },
+    Overlap {
+        line: u64,
+        i: usize,
+        other_line: u64,
+        other_i: usize,
+    },
+    OutOfOrder {
+        line: u64,
+        i: usize,
+    },
+    DuplicateIndex {
+        line: u64,
+        i: usize,
+    },
+    MissingIndex {
+        i: usize,
+    },
+    SupersededByUnparseable {
+        line: u64,
+        i: usize,
+        other_line: u64,
+    },
+}
+
+impl Problem {
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Problem::Unparseable { line, .. }
+            | Problem::EndBeforeStart { line, .. }
+            | Problem::Unterminated { line, .. }
+            | Problem::Overlap { line, .. }
+            | Problem::OutOfOrder { line, .. }
+            | Problem::DuplicateIndex { line, .. }
+            | Problem::SupersededByUnparseable { line, .. } => Some(*line),
+            Problem::MissingIndex { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unparseable { line, reason } => {
+                write!(f, "line {}: unparseable row: {}", line, reason)
+            }
+            Problem::EndBeforeStart { line, i } => {
+                write!(f, "line {}: record {} ends before it starts", line, i)
+            }
+            Problem::Unterminated { line, i } => write!(
+                f,
+                "line {}: record {} has no end but is not the newest record",
+                line, i
+            ),
+            Problem::Overlap {
+                line,
+                i,
+                other_line,
+                other_i,
+            } => write!(
+                f,
+                "line {}: record {} overlaps with record {} in line {}",
+                line, i, other_i, other_line
+            ),
+            Problem::OutOfOrder { line, i } => write!(
+                f,
+                "line {}: record {} starts before the record above it, records must be ordered oldest first",
+                line, i
+            ),
+            Problem::DuplicateIndex { line, i } => {
+                write!(f, "line {}: index {} is used more than once", line, i)
+            }
+            Problem::MissingIndex { i } => write!(f, "index {} is missing", i),
+            Problem::SupersededByUnparseable { line, i, other_line } => write!(
+                f,
+                "line {}: ongoing record {} may have been punched out by the unparseable row in line {}, --fix drops both",
+                line, i, other_line
+            ),
+        }
+    }
+}
+
+/// Splits the raw content of a card into rows without dropping any of them
+pub fn parse(contents: &str) -> Vec<Row> {
+    let mut reader = csv::reader(contents);
+
+    let mut rows = Vec::new();
+    let mut header = csv::default_header();
+    let mut raw = StringRecord::new();
+
+    loop {
+        match reader.read_record(&mut raw) {
+            Ok(false) => break,
+            Ok(true) if rows.is_empty() && csv::is_header(&raw) => header = raw.clone(),
+            Ok(true) => {
+                let record = raw.deserialize::<Record>(Some(&header));
+                let i = match &record {
+                    Ok(r) => Some(r.i),
+                    Err(_) => header
+                        .iter()
+                        .position(|column| column == Record::FIELDS[0])
+                        .and_then(|n| raw.get(n)?.trim().parse().ok()),
+                };
+                rows.push(Row {
+                    line: raw.position().map_or(0, |p| p.line()),
+                    i,
+                    record: record.map_err(|e| e.to_string()),
+                });
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                rows.push(Row {
+                    line,
+                    i: None,
+                    record: Err(e.to_string()),
+                });
+                // Errors that are not tied to a row can't be skipped
+                if e.position().is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // The reader reads a quoted field that was cut off up to the end of the card
+    if let Some(row) = rows
+        .last_mut()
+        .filter(|_| csv::ends_in_quoted_field(contents))
+    {
+        row.record = Err(csv::UNTERMINATED.into());
+    }
+
+    rows
+}
+
+pub fn diagnose(rows: &[Row]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let superseded = superseded_by_unparseable(rows);
+    for (line, i, other_line) in superseded.iter() {
+        problems.push(Problem::SupersededByUnparseable {
+            line: *line,
+            i: *i,
+            other_line: *other_line,
+        });
+    }
+
+    let parsed = rows
+        .iter()
+        .filter(|row| superseded.iter().all(|(line, ..)| *line != row.line))
+        .filter_map(|row| match &row.record {
+            Ok(r) => Some((row.line, r)),
+            Err(reason) => {
+                problems.push(Problem::Unparseable {
+                    line: row.line,
+                    reason: reason.clone(),
+                });
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    let parsed = supersede(parsed, |(_, r)| r);
+
+    for (line, r) in parsed.iter() {
+        if r.end.is_some_and(|end| end < r.start) {
+            problems.push(Problem::EndBeforeStart {
+                line: *line,
+                i: r.i,
+            });
+        }
+    }
+
+    // Only the newest record may be ongoing
+    let newest = parsed
+        .iter()
+        .map(|(_, r)| r.start)
+        .fold(None, max_timestamp);
+    for (line, r) in parsed.iter() {
+        if r.end.is_none() && Some(r.start) != newest {
+            problems.push(Problem::Unterminated {
+                line: *line,
+                i: r.i,
+            });
+        }
+    }
+
+    for pair in parsed.windows(2) {
+        let (_, above) = pair[0];
+        let (line, r) = pair[1];
+        if r.start < above.start {
+            problems.push(Problem::OutOfOrder { line, i: r.i });
+        }
+    }
+
+    // Broken records were reported above already and would only add noise here
+    let mut chronological = parsed
+        .iter()
+        .filter(|(_, r)| match r.end {
+            Some(end) => r.start <= end,
+            None => Some(r.start) == newest,
+        })
+        .collect::<Vec<_>>();
+    chronological.sort_by(|(_, a), (_, b)| a.start.partial_cmp(&b.start).unwrap());
+
+    let now = Timestamp::now();
+    let mut latest: Option<(u64, &Record)> = None;
+    for (line, r) in chronological {
+        if let Some((previous_line, previous)) = latest {
+            if r.start < previous.end.unwrap_or(now) {
+                problems.push(Problem::Overlap {
+                    line: *line,
+                    i: r.i,
+                    other_line: previous_line,
+                    other_i: previous.i,
+                });
+            }
+        }
+        if latest.is_none_or(|(_, previous)| r.end.unwrap_or(now) > previous.end.unwrap_or(now)) {
+            latest = Some((*line, r));
+        }
+    }
+
+    let mut lines_by_index = BTreeMap::new();
+    for (line, r) in parsed.iter() {
+        lines_by_index
+            .entry(r.i)
+            .or_insert_with(Vec::new)
+            .push(*line);
+    }
+    for (i, lines) in lines_by_index.iter() {
+        for line in lines.iter().skip(1) {
+            problems.push(Problem::DuplicateIndex { line: *line, i: *i });
+        }
+    }
+    let max_index = lines_by_index.keys().next_back().copied();
+    for i in (0..max_index.map_or(0, |max| max + 1)).filter(|i| !lines_by_index.contains_key(i)) {
+        problems.push(Problem::MissingIndex { i });
+    }
+
+    problems.sort_by_key(|p| p.line().unwrap_or(u64::MAX));
+
+    problems
+}
+
+/// Drops rows that can't be repaired or are superseded, trims overlaps and renumbers
+/// the records. Records are returned newest first.
+pub fn repair(rows: Vec<Row>) -> Vec<Record> {
+    let superseded = superseded_by_unparseable(&rows);
+    let records = rows
+        .into_iter()
+        .filter(|row| superseded.iter().all(|(line, ..)| *line != row.line))
+        .filter_map(|row| row.record.ok())
+        .collect::<Vec<_>>();
+    let mut records = supersede(records, |r| r)
+        .into_iter()
+        .filter(|r| r.end.is_none_or(|end| r.start <= end))
+        .collect::<Vec<_>>();
+
+    records.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+
+    // Only the newest record may be ongoing, older ones lack an end we could restore
+    let num_records = records.len();
+    let mut records = records
+        .into_iter()
+        .enumerate()
+        .filter(|(n, r)| r.end.is_some() || *n == num_records - 1)
+        .map(|(_, r)| r)
+        .collect::<Vec<_>>();
+
+    // Records that start at the same time as their predecessor are duplicates
+    records.dedup_by(|r, previous| r.start == previous.start);
+
+    for n in 1..records.len() {
+        let next_start = records[n].start;
+        let previous = &mut records[n - 1];
+        if previous.end.is_some_and(|end| end > next_start) {
+            previous.end = Some(next_start);
+        }
+    }
+
+    Record::index_chronologically(&mut records);
+
+    records
+}
+
+/// Replaces the rows of ongoing records by the rows that punched them out, at the
+/// position of the former. Punching out appends the record again with the same index
+/// and start. Other rows with the same index are duplicates and kept.
+pub fn supersede<T, F>(rows: Vec<T>, record: F) -> Vec<T>
+where
+    F: Fn(&T) -> &Record,
+{
+    // Positions of the ongoing records by their index
+    let mut ongoing = HashMap::new();
+    let mut latest: Vec<T> = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let (i, start, is_ongoing) = {
+            let r = record(&row);
+            (r.i, r.start, r.end.is_none())
+        };
+
+        match ongoing.get(&i) {
+            Some(&n) if record(&latest[n]).start == start => {
+                latest[n] = row;
+                if !is_ongoing {
+                    ongoing.remove(&i);
+                }
+            }
+            _ => {
+                if is_ongoing {
+                    ongoing.insert(i, latest.len());
+                }
+                latest.push(row);
+            }
+        }
+    }
+
+    latest
+}
+
+// Punching out appends a row that supersedes the row of the ongoing record. If that row
+// can't be parsed, the ongoing record must not come back once the row is dropped. Returns
+// the line and index of such records along with the line of the unparseable row.
+fn superseded_by_unparseable(rows: &[Row]) -> Vec<(u64, usize, u64)> {
+    let mut ongoing = HashMap::new();
+    let mut superseded = Vec::new();
+
+    for row in rows {
+        match (&row.record, row.i) {
+            (Ok(r), _) if r.end.is_none() => {
+                ongoing.insert(r.i, (row.line, r.start));
+            }
+            (Ok(r), _) => {
+                if ongoing
+                    .get(&r.i)
+                    .is_some_and(|(_, start)| *start == r.start)
+                {
+                    ongoing.remove(&r.i);
+                }
+            }
+            (Err(_), Some(i)) => {
+                if let Some((line, _)) = ongoing.remove(&i) {
+                    superseded.push((line, i, row.line));
+                }
+            }
+            (Err(_), None) => {}
+        }
+    }
+
+    superseded
+}
+
+fn max_timestamp(acc: Option<Timestamp>, t: Timestamp) -> Option<Timestamp> {
+    match acc {
+        Some(max) if max >= t => Some(max),
+        _ => Some(t),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{card, record, row, summary};
+
+    fn problems(rows: &[String]) -> Vec<String> {
+        diagnose(&parse(&card(rows)))
+            .iter()
+            .map(|p| format!("{:?}", p))
+            .collect()
+    }
+
+    #[test]
+    fn punched_out_records_are_no_problem() {
+        let rows = [
+            row(0, 8, Some(9), "a"),
+            row(1, 10, None, "b"),
+            row(1, 10, Some(11), "b"),
+            row(2, 12, None, "c"),
+        ];
+
+        assert!(problems(&rows).is_empty());
+        assert_eq!(
+            summary(&repair(parse(&card(&rows)))),
+            [
+                (2, None, "c".into()),
+                (1, Some("11".into()), "b".into()),
+                (0, Some("09".into()), "a".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn unparseable_rows() {
+        let rows = [row(0, 8, Some(9), "a"), "1,yesterday,,b,\n".into()];
+
+        assert!(matches!(
+            diagnose(&parse(&card(&rows))).as_slice(),
+            [Problem::Unparseable { line: 4, .. }]
+        ));
+        assert_eq!(parse(&card(&rows))[1].i, Some(1));
+    }
+
+    #[test]
+    fn records_that_end_before_they_start() {
+        let rows = [row(0, 9, Some(8), "a")];
+
+        assert_eq!(problems(&rows), ["EndBeforeStart { line: 3, i: 0 }"]);
+    }
+
+    #[test]
+    fn unterminated_records() {
+        let rows = [row(0, 8, None, "a"), row(1, 10, Some(11), "b")];
+
+        assert_eq!(problems(&rows), ["Unterminated { line: 3, i: 0 }"]);
+    }
+
+    #[test]
+    fn overlapping_records() {
+        let rows = [row(0, 8, Some(10), "a"), row(1, 9, Some(11), "b")];
+
+        assert_eq!(
+            problems(&rows),
+            ["Overlap { line: 4, i: 1, other_line: 3, other_i: 0 }"]
+        );
+    }
+
+    #[test]
+    fn records_out_of_order() {
+        let rows = [row(0, 10, Some(11), "a"), row(1, 8, Some(9), "b")];
+
+        assert_eq!(problems(&rows), ["OutOfOrder { line: 4, i: 1 }"]);
+    }
+
+    #[test]
+    fn duplicate_and_missing_indices() {
+        let rows = [row(0, 8, Some(9), "a"), row(0, 10, Some(11), "b")];
+        assert_eq!(problems(&rows), ["DuplicateIndex { line: 4, i: 0 }"]);
+
+        let rows = [row(0, 8, Some(9), "a"), row(2, 10, Some(11), "b")];
+        assert_eq!(problems(&rows), ["MissingIndex { i: 1 }"]);
+    }
+
+    #[test]
+    fn ongoing_records_punched_out_by_unparseable_rows() {
+        let rows = [
+            row(0, 8, Some(9), "a"),
+            row(1, 10, None, "b"),
+            "1,2026-10-01T10:00:00+00:00,noon,b,\n".into(),
+        ];
+
+        assert!(matches!(
+            diagnose(&parse(&card(&rows))).as_slice(),
+            [
+                Problem::SupersededByUnparseable {
+                    line: 4,
+                    i: 1,
+                    other_line: 5
+                },
+                Problem::Unparseable { line: 5, .. }
+            ]
+        ));
+        // The ongoing record must not come back as if it was never punched out
+        assert_eq!(
+            summary(&repair(parse(&card(&rows)))),
+            [(0, Some("09".into()), "a".into())]
+        );
+    }
+
+    #[test]
+    fn torn_rows() {
+        // Punching out was interrupted while appending the row
+        let rows = [
+            row(0, 8, Some(9), "a"),
+            row(1, 10, None, "b"),
+            row(1, 10, Some(11), "\"b cut off"),
+        ];
+
+        assert!(matches!(
+            diagnose(&parse(&card(&rows))).as_slice(),
+            [
+                Problem::SupersededByUnparseable {
+                    line: 4,
+                    i: 1,
+                    other_line: 5
+                },
+                Problem::Unparseable { line: 5, reason }
+            ] if reason == csv::UNTERMINATED
+        ));
+        assert_eq!(
+            summary(&repair(parse(&card(&rows)))),
+            [(0, Some("09".into()), "a".into())]
+        );
+    }
+
+    #[test]
+    fn repairing_broken_rows() {
+        let rows = [
+            row(0, 7, None, "unterminated"),
+            row(1, 8, Some(10), "trimmed"),
+            "2,noon,,unparseable,\n".into(),
+            row(3, 9, Some(11), "kept"),
+            row(4, 12, Some(11), "reversed"),
+            row(5, 13, None, "ongoing"),
+        ];
+
+        assert_eq!(
+            summary(&repair(parse(&card(&rows)))),
+            [
+                (2, None, "ongoing".into()),
+                (1, Some("11".into()), "kept".into()),
+                (0, Some("09".into()), "trimmed".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn superseding_rows() {
+        let records = vec![
+            record(0, 8, None, "a"),
+            record(1, 9, Some(10), "b"),
+            record(0, 8, Some(9), "a"),
+            record(1, 9, Some(11), "duplicate"),
+        ];
+
+        // Only ongoing records are superseded, at their position
+        assert_eq!(
+            summary(&supersede(records, |r| r)),
+            [
+                (0, Some("09".into()), "a".into()),
+                (1, Some("10".into()), "b".into()),
+                (1, Some("11".into()), "duplicate".into())
+            ]
+        );
+    }
+}
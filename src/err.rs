@@ -1,10 +1,11 @@
-use ansi_term::Colour::Red;
+use ansi_term::Colour::{Red, Yellow};
 
 error_chain! {
     foreign_links {
         Clap(::clap::Error);
         Io(::std::io::Error);
         Csv(::csv::Error);
+        Sqlite(::rusqlite::Error);
     }
 
     errors {
@@ -20,6 +21,39 @@ error_chain! {
         InvalidFile(path: String) {
             display("Invalid file: {}", path),
         }
+        InvalidCardName(name: String) {
+            display("Invalid card name: {}. Use letters, digits, '-' and '_' only", name),
+        }
+        CardNotFound(name: String) {
+            display("Card does not exist: {}. Create it with `punch card new {}`", name, name),
+        }
+        CardAlreadyExists(name: String) {
+            display("Card already exists: {}", name),
+        }
+        CannotRemoveActiveCard(name: String) {
+            display("Cannot remove the active card: {}. Switch to another card first", name),
+        }
+        CardNotEmpty(name: String) {
+            display("Card is not empty: {}. Use --force to remove it anyway", name),
+        }
+        MalformedRecord(line: u64, field: String, reason: String) {
+            display("Malformed record in line {}, field {}: {}. Run `punch doctor` for details", line, field, reason),
+        }
+        EditorFailed {
+            display("Editor returned non-zero exit code"),
+        }
+        EditDiscarded(path: String) {
+            display("Card left unchanged. Your edits are kept in {}", path),
+        }
+        CardModifiedWhileEditing(path: String) {
+            display("Card was modified by another process while editing. Your edits are kept in {}", path),
+        }
+        LockTimeout(path: String) {
+            display("Timed out waiting for lock: {}. Is another punch process running?", path),
+        }
+        UnsupportedCardVersion(version: String) {
+            display("Unsupported card version: {}. The card may have been written by a newer version of punch", version),
+        }
         FileIsEmpty {
             display("File is empty"),
         }
@@ -29,15 +63,80 @@ error_chain! {
         IncorrectCardStateForOut {
             display("Cannot punch out. Did you punch in before?"),
         }
+        IncorrectCardStateForCancel {
+            display("Cannot cancel. Is there an ongoing record?"),
+        }
+        CardIsCorrupted(name: String) {
+            display("Card is corrupted: {}. Run `punch doctor` for details", name),
+        }
+        InvalidTimestamp(input: String) {
+            display("Failed to parse time: {}. Expected e.g. \"09:15\", \"yesterday 17:30\", \"monday\", \"2020-02-14\", \"-2w\" or an RFC 3339 timestamp", input),
+        }
+        InvalidMonth(month: String) {
+            display("Invalid month: {}. Expected a month like 2020-02", month),
+        }
+        InvalidImport(format: String, reason: String) {
+            display("Failed to import {} file: {}", format, reason),
+        }
+        InvalidTimezone(name: String) {
+            display("Unknown timezone: {}. Expected an IANA name like \"Europe/Berlin\" or \"local\"", name),
+        }
+        InvalidDuration(input: String) {
+            display("Failed to parse duration: {}. Expected e.g. \"15min\" or \"1h30m\"", input),
+        }
+        PunchInFuture(time: String) {
+            display("Cannot punch in the future: {}", time),
+        }
+        PunchInBeforeLastPunchOut(time: String) {
+            display("Cannot punch in before the end of the previous record: {}", time),
+        }
+        PunchOutBeforePunchIn(time: String) {
+            display("Cannot punch out before the start of the ongoing record: {}", time),
+        }
+        RecordEndsBeforeStart {
+            display("Cannot add a record that ends before it starts"),
+        }
+        RecordOverlaps(i: usize) {
+            display("Record overlaps with existing record {}", i),
+        }
+        NothingToUndo {
+            display("Nothing to undo"),
+        }
+        NothingToRedo {
+            display("Nothing to redo"),
+        }
+        CardModifiedSinceOperation(op: String) {
+            display("Card was modified outside of punch since the last {} operation", op),
+        }
         InvalidTimeInterval {
             display("Failed to parse time interval"),
         }
         InvalidRoundingDirection {
             display("Failed to parse rounding direction"),
         }
+        InvalidProjects(path: String) {
+            display("Failed to parse projects in {}", path),
+        }
+        InvalidConfig(path: String) {
+            display("Failed to parse config in {}", path),
+        }
+        ClientNotFound(name: String) {
+            display("No project of client {} found in ~/.punch/projects.toml", name),
+        }
+        ProjectNotFound(name: String) {
+            display("Project not found: {}. Projects are configured in ~/.punch/projects.toml", name),
+        }
     }
 }
 
+pub fn handle_warning(message: &str) {
+    eprintln!("{}: {}", Yellow.paint("[punch warning]"), message);
+}
+
 pub fn handle_error(error: &Error) {
     eprintln!("{}: {}", Red.paint("[punch error]"), error);
+
+    for cause in error.iter().skip(1) {
+        eprintln!("  caused by: {}", cause);
+    }
 }
@@ -0,0 +1,176 @@
+use ::csv::WriterBuilder;
+use serde::Serialize;
+
+use crate::err::*;
+use crate::project::Projects;
+use crate::record::Record;
+use crate::round::RoundingOptions;
+use crate::time::Timestamp;
+
+arg_enum! {
+    #[derive(Clone, Copy, Debug)]
+    pub enum ExportFormat {
+        Json,
+        Ndjson,
+        Csv,
+        Ics,
+    }
+}
+
+// Content lines of iCalendar files must not be longer than this, in bytes
+const ICS_MAX_LINE_LENGTH: usize = 75;
+
+/// A record as exported for other programs. Timestamps are in RFC 3339 format and
+/// durations in seconds. Ongoing records have no end and last until now.
+#[derive(Serialize)]
+pub struct ExportedRecord<'a> {
+    i: usize,
+    start: String,
+    end: Option<String>,
+    duration: usize,
+    rounded_duration: usize,
+    note: Option<&'a str>,
+    project: Option<&'a str>,
+}
+
+impl<'a> ExportedRecord<'a> {
+    pub fn new(r: &'a Record, rounding_opts: &RoundingOptions) -> Self {
+        ExportedRecord {
+            i: r.i,
+            start: r.start.to_rfc3339(),
+            end: r.end.map(|end| end.to_rfc3339()),
+            duration: r.duration().in_seconds(),
+            rounded_duration: r.duration().round(rounding_opts).in_seconds(),
+            note: r.note.as_deref(),
+            project: r.project.as_deref(),
+        }
+    }
+}
+
+/// Serializes records of a card in the order they are given. Durations are rounded
+/// like the projects of the records, or by the rounding options.
+pub fn export(
+    card_name: &str,
+    records: &[Record],
+    format: ExportFormat,
+    projects: &Projects,
+    rounding_opts: &RoundingOptions,
+) -> Result<String> {
+    if let ExportFormat::Ics = format {
+        return Ok(to_ics(card_name, records));
+    }
+
+    let records = records
+        .iter()
+        .map(|r| ExportedRecord::new(r, r.rounding_in(projects, rounding_opts)))
+        .collect::<Vec<_>>();
+
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(&records)
+            .chain_err(|| "Could not serialize records")?
+            + "\n"),
+        ExportFormat::Ndjson => records
+            .iter()
+            .map(|r| {
+                serde_json::to_string(r)
+                    .map(|line| line + "\n")
+                    .chain_err(|| "Could not serialize records")
+            })
+            .collect(),
+        ExportFormat::Ics => unreachable!(),
+        ExportFormat::Csv => {
+            // Headers are written along with the first record, so they are written manually
+            let mut writer = WriterBuilder::new().has_headers(false).from_writer(vec![]);
+            writer.write_record([
+                "i",
+                "start",
+                "end",
+                "duration",
+                "rounded_duration",
+                "note",
+                "project",
+            ])?;
+            for r in records.iter() {
+                writer.serialize(r)?;
+            }
+
+            let contents = writer
+                .into_inner()
+                .map_err(|e| e.into_error())
+                .chain_err(|| "Could not serialize records")?;
+
+            String::from_utf8(contents).chain_err(|| "Could not serialize records")
+        }
+    }
+}
+
+/// Writes each record as an event. Ongoing records are left out, unless their end is
+/// set to now beforehand.
+fn to_ics(card_name: &str, records: &[Record]) -> String {
+    let now = Timestamp::now().to_ics();
+
+    let mut lines = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        format!("PRODID:-//punch//punch {}//EN", env!("CARGO_PKG_VERSION")),
+    ];
+
+    for r in records.iter() {
+        let end = match r.end {
+            Some(end) => end,
+            None => continue,
+        };
+        // Records without note are named after their project, or their card otherwise
+        let summary = r
+            .note
+            .as_deref()
+            .or(r.project.as_deref())
+            .unwrap_or(card_name);
+
+        lines.push(String::from("BEGIN:VEVENT"));
+        // Indices change when records are inserted before others, while records of a
+        // card never start at the same time. Starts are formatted to the second only,
+        // which records that were punched within the same second share.
+        lines.push(format!(
+            "UID:{}-{}@punch",
+            card_name,
+            r.start.timestamp_millis()
+        ));
+        lines.push(format!("DTSTAMP:{}", now));
+        lines.push(format!("DTSTART:{}", r.start.to_ics()));
+        lines.push(format!("DTEND:{}", end.to_ics()));
+        lines.push(format!("SUMMARY:{}", escape_ics(summary)));
+        if let Some(project) = &r.project {
+            lines.push(format!("CATEGORIES:{}", escape_ics(project)));
+        }
+        lines.push(String::from("END:VEVENT"));
+    }
+
+    lines.push(String::from("END:VCALENDAR"));
+
+    lines.iter().map(|line| fold_ics(line) + "\r\n").collect()
+}
+
+fn escape_ics(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Splits long lines into several, each continued line starts with a space
+fn fold_ics(line: &str) -> String {
+    let mut folded = String::new();
+    let mut line_length = 0;
+
+    for c in line.chars() {
+        if line_length + c.len_utf8() > ICS_MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            line_length = 1;
+        }
+        folded.push(c);
+        line_length += c.len_utf8();
+    }
+
+    folded
+}
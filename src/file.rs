@@ -0,0 +1,56 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+
+use crate::err::*;
+
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An exclusive advisory lock that is released when dropped
+pub struct FileLock(File);
+
+impl FileLock {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .chain_err(|| "Failed to open lock file")?;
+
+        let started = Instant::now();
+        while file.try_lock_exclusive().is_err() {
+            if started.elapsed() >= LOCK_TIMEOUT {
+                return Err(ErrorKind::LockTimeout(path.display().to_string()).into());
+            }
+            thread::sleep(LOCK_RETRY_INTERVAL);
+        }
+
+        Ok(FileLock(file))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock as well, unlocking explicitly is just tidier
+        let _ = FileExt::unlock(&self.0);
+    }
+}
+
+/// Writes to a temporary file next to the target and renames it, so that the target is
+/// either left untouched or replaced completely
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut tmp = File::create(&tmp_path).chain_err(|| "Failed to create temporary file")?;
+    tmp.write_all(contents.as_bytes())?;
+    tmp.sync_all()?;
+
+    fs::rename(&tmp_path, path).chain_err(|| "Failed to replace file")
+}
@@ -1,9 +1,36 @@
+use crate::project::Projects;
 use crate::round::RoundingOptions;
-use crate::time::Interval;
+use crate::time::{Calendar, Span, Timestamp};
+
+arg_enum! {
+    #[derive(Clone, Copy, Debug)]
+    pub enum ShowFormat {
+        Text,
+        Json,
+    }
+}
+
+// arg_enum! does not accept #[default] on variants
+#[allow(clippy::derivable_impls)]
+impl Default for ShowFormat {
+    fn default() -> Self {
+        ShowFormat::Text
+    }
+}
 
 #[derive(Default)]
 pub struct CardFormattingOptions {
-    pub interval: Interval,
+    pub span: Span,
+    pub format: ShowFormat,
+    /// Only records with this tag are shown
+    pub tag: Option<String>,
+    /// Only records that overlap with this range are shown
+    pub since: Option<Timestamp>,
+    pub until: Option<Timestamp>,
+    /// Records that span several groups are split so that each group gets its part
+    pub split: bool,
+    /// Defines the weeks that records are grouped by
+    pub calendar: Calendar,
     pub record_opts: RecordFormattingOptions,
 }
 
@@ -12,6 +39,8 @@ pub struct RecordFormattingOptions {
     pub precise: bool,
     pub timezone: bool,
     pub rounding_opts: RoundingOptions,
+    /// Used to round and bill records of projects
+    pub projects: Projects,
 }
 
 impl Default for RecordFormattingOptions {
@@ -21,6 +50,7 @@ impl Default for RecordFormattingOptions {
             precise: false,
             timezone: true,
             rounding_opts: Default::default(),
+            projects: Default::default(),
         }
     }
 }
@@ -0,0 +1,597 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use ::csv::ReaderBuilder;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::err::*;
+use crate::project::Projects;
+use crate::record::Record;
+use crate::time::Timestamp;
+
+arg_enum! {
+    #[derive(Clone, Copy, Debug)]
+    pub enum ImportFormat {
+        Toggl,
+        Timewarrior,
+        Watson,
+        Ics,
+    }
+}
+
+/// A time entry of a detailed report as exported by Toggl
+#[derive(Deserialize)]
+struct TogglEntry {
+    #[serde(rename = "Project", default)]
+    project: Option<String>,
+    #[serde(rename = "Description", default)]
+    description: Option<String>,
+    #[serde(rename = "Start date")]
+    start_date: String,
+    #[serde(rename = "Start time")]
+    start_time: String,
+    #[serde(rename = "End date")]
+    end_date: String,
+    #[serde(rename = "End time")]
+    end_time: String,
+    #[serde(rename = "Tags", default)]
+    tags: Option<String>,
+}
+
+/// An interval as printed by `timew export`
+#[derive(Deserialize)]
+struct TimewarriorInterval {
+    start: String,
+    end: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    annotation: Option<String>,
+}
+
+/// A frame as printed by `watson log --json`, or as stored in Watson's frames file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WatsonFrame {
+    Logged {
+        project: String,
+        start: String,
+        stop: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    // Start, stop, project, id, tags and time of the last update
+    Stored(i64, i64, String, IgnoredAny, Vec<String>, IgnoredAny),
+}
+
+#[derive(Default)]
+struct IcsEvent {
+    start: Option<Timestamp>,
+    end: Option<Timestamp>,
+    summary: Option<String>,
+    category: Option<String>,
+}
+
+/// Reason why an imported record is not added to the card
+#[derive(Debug)]
+pub enum Conflict {
+    Ongoing,
+    EndsBeforeStart,
+    InFuture,
+    Duplicate,
+    Overlaps(Record),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Ongoing => write!(f, "has not ended yet"),
+            Conflict::EndsBeforeStart => write!(f, "ends before it starts"),
+            Conflict::InFuture => write!(f, "ends in the future"),
+            Conflict::Duplicate => write!(f, "already exists"),
+            Conflict::Overlaps(r) => write!(
+                f,
+                "overlaps with the record that starts at {}",
+                r.start.format("%F %T")
+            ),
+        }
+    }
+}
+
+/// The records of a card after an import, newest first, along with the records that
+/// were added and those that were skipped
+pub struct Merge {
+    pub records: Vec<Record>,
+    pub added: Vec<Record>,
+    pub skipped: Vec<(Record, Conflict)>,
+}
+
+/// Parses the records of another time tracker. Tags become #tags in the note. Projects
+/// that are not in the registry become +projects in the note instead.
+pub fn parse(contents: &str, format: ImportFormat, projects: &Projects) -> Result<Vec<Record>> {
+    let invalid =
+        |reason: String| Error::from(ErrorKind::InvalidImport(format.to_string(), reason));
+
+    match format {
+        ImportFormat::Toggl => {
+            // Spreadsheet programs prepend a byte order mark
+            let contents = contents.trim_start_matches('\u{feff}');
+
+            ReaderBuilder::new()
+                .from_reader(contents.as_bytes())
+                .deserialize()
+                .map(|entry| {
+                    let entry: TogglEntry = entry.map_err(|e| invalid(e.to_string()))?;
+                    let start = toggl_timestamp(&entry.start_date, &entry.start_time)
+                        .ok_or_else(|| invalid(format!("invalid start {}", entry.start_date)))?;
+                    let end = toggl_timestamp(&entry.end_date, &entry.end_time)
+                        .ok_or_else(|| invalid(format!("invalid end {}", entry.end_date)))?;
+                    let tags = entry.tags.unwrap_or_default();
+                    let tags = tags.split(',').map(str::trim).filter(|t| !t.is_empty());
+
+                    Ok(to_record(
+                        start,
+                        Some(end),
+                        entry.description,
+                        entry.project,
+                        tags,
+                        projects,
+                    ))
+                })
+                .collect()
+        }
+        ImportFormat::Timewarrior => {
+            let intervals: Vec<TimewarriorInterval> =
+                serde_json::from_str(contents).map_err(|e| invalid(e.to_string()))?;
+
+            intervals
+                .into_iter()
+                .map(|interval| {
+                    let start = ics_timestamp(&interval.start)
+                        .ok_or_else(|| invalid(format!("invalid start {}", interval.start)))?;
+                    let end = interval
+                        .end
+                        .map(|end| {
+                            ics_timestamp(&end)
+                                .ok_or_else(|| invalid(format!("invalid end {}", end)))
+                        })
+                        .transpose()?;
+
+                    // Tags that name a project of the registry are used as project
+                    let project = interval
+                        .tags
+                        .iter()
+                        .find(|t| projects.get(t).is_some())
+                        .cloned();
+                    let tags = interval
+                        .tags
+                        .iter()
+                        .filter(|t| Some(*t) != project.as_ref())
+                        .cloned()
+                        .collect::<Vec<_>>();
+
+                    Ok(to_record(
+                        start,
+                        end,
+                        interval.annotation,
+                        project,
+                        tags.iter().map(String::as_str),
+                        projects,
+                    ))
+                })
+                .collect()
+        }
+        ImportFormat::Watson => {
+            let frames: Vec<WatsonFrame> =
+                serde_json::from_str(contents).map_err(|e| invalid(e.to_string()))?;
+
+            frames
+                .into_iter()
+                .map(|frame| {
+                    let (start, end, project, tags) = match frame {
+                        WatsonFrame::Logged {
+                            project,
+                            start,
+                            stop,
+                            tags,
+                        } => (
+                            Timestamp::try_from(start.as_str())?,
+                            Timestamp::try_from(stop.as_str())?,
+                            project,
+                            tags,
+                        ),
+                        WatsonFrame::Stored(start, stop, project, _, tags, _) => (
+                            Timestamp::from_unix(start)
+                                .ok_or_else(|| invalid(format!("invalid start {}", start)))?,
+                            Timestamp::from_unix(stop)
+                                .ok_or_else(|| invalid(format!("invalid stop {}", stop)))?,
+                            project,
+                            tags,
+                        ),
+                    };
+                    let tags = tags.iter().map(String::as_str);
+
+                    Ok(to_record(
+                        start,
+                        Some(end),
+                        None,
+                        Some(project),
+                        tags,
+                        projects,
+                    ))
+                })
+                .collect()
+        }
+        ImportFormat::Ics => parse_ics(contents, projects).map_err(invalid),
+    }
+}
+
+// Events are read from DTSTART, DTEND, SUMMARY and CATEGORIES. Times with a TZID are
+// read as local times.
+fn parse_ics(contents: &str, projects: &Projects) -> std::result::Result<Vec<Record>, String> {
+    // Long lines are folded by a line break followed by a space or tab
+    let contents = contents
+        .replace("\r\n", "\n")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let mut records = Vec::new();
+    let mut event: Option<IcsEvent> = None;
+
+    for line in contents.lines() {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.split(';').next().unwrap_or_default(), value),
+            None => continue,
+        };
+        let time = || ics_timestamp(value).ok_or_else(|| format!("invalid time {}", value));
+
+        match (name.to_uppercase().as_str(), event.as_mut()) {
+            ("BEGIN", None) if value == "VEVENT" => event = Some(IcsEvent::default()),
+            ("END", Some(_)) if value == "VEVENT" => {
+                let e = event.take().unwrap();
+                let start = e.start.ok_or("event without DTSTART")?;
+                // Our own export names records without note after their project
+                let is_named_after_project = e.summary.is_some() && e.summary == e.category;
+                let note = e.summary.filter(|_| !is_named_after_project);
+
+                records.push(to_record(
+                    start,
+                    e.end,
+                    note,
+                    e.category,
+                    std::iter::empty(),
+                    projects,
+                ));
+            }
+            ("DTSTART", Some(e)) => e.start = Some(time()?),
+            ("DTEND", Some(e)) => e.end = Some(time()?),
+            ("SUMMARY", Some(e)) => e.summary = Some(unescape_ics(value)),
+            ("CATEGORIES", Some(e)) => {
+                e.category = value.split(',').next().map(unescape_ics);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(records)
+}
+
+fn toggl_timestamp(date: &str, time: &str) -> Option<Timestamp> {
+    let date = NaiveDate::parse_from_str(date.trim(), "%F").ok()?;
+    let time = NaiveTime::parse_from_str(time.trim(), "%T").ok()?;
+
+    Timestamp::from_local(date.and_time(time))
+}
+
+// Parses times like 20200214T091500Z in UTC, and like 20200214T091500 or 20200214 in
+// local time
+fn ics_timestamp(value: &str) -> Option<Timestamp> {
+    if let Some(value) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .ok()
+            .map(Timestamp::from_utc);
+    }
+
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .and_then(Timestamp::from_local)
+}
+
+fn unescape_ics(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        // Notes are single lines
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push(' '),
+            Some(escaped) => unescaped.push(escaped),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+// Composes the note of an imported record from its description and tags
+fn to_record<'a>(
+    start: Timestamp,
+    end: Option<Timestamp>,
+    description: Option<String>,
+    project: Option<String>,
+    tags: impl Iterator<Item = &'a str>,
+    projects: &Projects,
+) -> Record {
+    let (project, unknown_project) = match project {
+        Some(p) if projects.get(&p).is_some() => (Some(p), None),
+        p => (None, p),
+    };
+
+    // Tags can't contain whitespace
+    let tokens = unknown_project
+        .iter()
+        .map(|p| format!("+{}", p.split_whitespace().collect::<Vec<_>>().join("-")))
+        .chain(tags.map(|t| format!("#{}", t.split_whitespace().collect::<Vec<_>>().join("-"))));
+
+    let note = description
+        .into_iter()
+        .map(|d| d.trim().to_string())
+        .chain(tokens)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Record {
+        i: 0,
+        start,
+        end,
+        note: Some(note).filter(|n| !n.is_empty()),
+        project,
+    }
+}
+
+/// Adds the imported records that don't conflict with existing records or with each
+/// other, and indexes all records chronologically
+pub fn merge(existing: Vec<Record>, mut imported: Vec<Record>) -> Merge {
+    let now = Timestamp::now();
+    imported.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+
+    let mut added: Vec<Record> = Vec::new();
+    let mut skipped = Vec::new();
+
+    for r in imported {
+        let end = match r.end {
+            Some(end) => end,
+            None => {
+                skipped.push((r, Conflict::Ongoing));
+                continue;
+            }
+        };
+
+        let overlapping = Record::find_overlap(existing.iter().chain(added.iter()), r.start, end);
+        let is_duplicate = existing
+            .iter()
+            .chain(added.iter())
+            .any(|o| o.start == r.start && o.end == Some(end));
+
+        let conflict = if end < r.start {
+            Conflict::EndsBeforeStart
+        } else if end > now {
+            Conflict::InFuture
+        } else if is_duplicate {
+            Conflict::Duplicate
+        } else if let Some(o) = overlapping {
+            Conflict::Overlaps(o.clone())
+        } else {
+            added.push(r);
+            continue;
+        };
+        skipped.push((r, conflict));
+    }
+
+    let mut records = existing;
+    records.extend(added.iter().cloned());
+    Record::index_chronologically(&mut records);
+
+    // Added records neither overlap with nor duplicate others, so their start and end
+    // tell them apart
+    let added = records
+        .iter()
+        .rev()
+        .filter(|r| added.iter().any(|a| a.start == r.start && a.end == r.end))
+        .cloned()
+        .collect();
+
+    Merge {
+        records,
+        added,
+        skipped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{at, record};
+    use crate::time::Duration;
+
+    fn projects() -> Projects {
+        Projects::parse("[ACME]\nrate = 100.0\n").unwrap()
+    }
+
+    fn notes(records: &[Record]) -> Vec<(Option<&str>, Option<&str>)> {
+        records
+            .iter()
+            .map(|r| (r.note.as_deref(), r.project.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn toggl_reports() {
+        let contents = "\u{feff}User,Project,Description,Start date,Start time,End date,End time,Tags\n\
+                        Ada,ACME,Review,2026-10-01,09:00:00,2026-10-01,10:30:00,\"urgent, code review\"\n\
+                        Ada,Side Project,,2026-10-01,11:00:00,2026-10-01,12:00:00,\n";
+        let records = parse(contents, ImportFormat::Toggl, &projects()).unwrap();
+
+        assert_eq!(
+            notes(&records),
+            [
+                (Some("Review #urgent #code-review"), Some("ACME")),
+                (Some("+Side-Project"), None)
+            ]
+        );
+        let start = NaiveDate::from_ymd_opt(2026, 10, 1)
+            .unwrap()
+            .and_hms_opt(9, 0, 0);
+        assert!(records[0].start == Timestamp::from_local(start.unwrap()).unwrap());
+
+        let contents = "Project,Description,Start date,Start time,End date,End time\n\
+                        ACME,Review,2026-10-01,9 am,2026-10-01,10:30:00\n";
+        assert!(parse(contents, ImportFormat::Toggl, &projects()).is_err());
+    }
+
+    #[test]
+    fn timewarrior_exports() {
+        let contents = r#"[
+            {"id": 2, "start": "20261001T090000Z", "end": "20261001T100000Z",
+             "tags": ["meeting", "ACME"], "annotation": "Standup"},
+            {"id": 1, "start": "20261001T110000Z", "tags": ["open"]}
+        ]"#;
+        let records = parse(contents, ImportFormat::Timewarrior, &projects()).unwrap();
+
+        assert_eq!(
+            notes(&records),
+            [
+                (Some("Standup #meeting"), Some("ACME")),
+                (Some("#open"), None)
+            ]
+        );
+        assert!(records[0].start == at(9));
+        assert!(records[0].end == Some(at(10)));
+        assert!(records[1].end.is_none());
+
+        assert!(parse("{}", ImportFormat::Timewarrior, &projects()).is_err());
+    }
+
+    #[test]
+    fn watson_logs_and_frames() {
+        let log = r#"[{"id": "f1", "project": "ACME", "start": "2026-10-01T11:00:00+02:00",
+                       "stop": "2026-10-01T12:00:00+02:00", "tags": ["calls"]}]"#;
+        let records = parse(log, ImportFormat::Watson, &projects()).unwrap();
+
+        assert_eq!(notes(&records), [(Some("#calls"), Some("ACME"))]);
+        assert!(records[0].start == at(9));
+
+        let frames = r#"[[1790838000, 1790841600, "side", "f2", ["x"], 1790841600]]"#;
+        let records = parse(frames, ImportFormat::Watson, &projects()).unwrap();
+
+        assert_eq!(notes(&records), [(Some("+side #x"), None)]);
+        assert!(records[0].start == at(7));
+        assert!(records[0].end == Some(at(8)));
+    }
+
+    #[test]
+    fn ics_events() {
+        let contents = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        DTSTART:20261001T090000Z\r\n\
+                        DTEND:20261001T100000Z\r\n\
+                        SUMMARY:Review\\, with a long\r\n  summary\\nthat is folded\r\n\
+                        CATEGORIES:ACME,other\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VEVENT\r\n\
+                        DTSTART;TZID=Europe/Berlin:20261001T110000\r\n\
+                        SUMMARY:ACME\r\n\
+                        CATEGORIES:ACME\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let records = parse_ics(contents, &projects()).unwrap();
+
+        assert_eq!(
+            notes(&records),
+            [
+                (
+                    Some("Review, with a long summary that is folded"),
+                    Some("ACME")
+                ),
+                // Named after the project by our own export
+                (None, Some("ACME"))
+            ]
+        );
+        assert!(records[0].end == Some(at(10)));
+        assert!(records[1].end.is_none());
+
+        let contents = "BEGIN:VEVENT\nSUMMARY:Review\nEND:VEVENT\n";
+        assert!(parse_ics(contents, &projects()).is_err());
+        let contents = "BEGIN:VEVENT\nDTSTART:tomorrow\nEND:VEVENT\n";
+        assert!(parse_ics(contents, &projects()).is_err());
+    }
+
+    #[test]
+    fn merging_imported_records() {
+        let existing = vec![record(1, 10, None, ""), record(0, 8, Some(9), "")];
+
+        let future = Record {
+            end: Some(Timestamp::now() + Duration::one_hour()),
+            ..record(0, 7, None, "")
+        };
+        let imported = vec![
+            record(0, 6, Some(7), ""),
+            record(0, 5, None, ""),
+            record(0, 4, Some(3), ""),
+            future,
+            record(0, 8, Some(9), ""),
+            record(0, 8, Some(10), ""),
+            record(0, 6, Some(8), ""),
+            record(0, 11, Some(12), ""),
+        ];
+        let merge = merge(existing, imported);
+
+        let conflicts = merge
+            .skipped
+            .iter()
+            .map(|(r, conflict)| (r.start.format("%H"), conflict.to_string()))
+            .collect::<Vec<_>>();
+        let start_of = |hour: &str| {
+            format!(
+                "overlaps with the record that starts at 2026-10-01 {}:00:00",
+                hour
+            )
+        };
+        assert_eq!(
+            conflicts,
+            [
+                ("04".into(), "ends before it starts".into()),
+                ("05".into(), "has not ended yet".into()),
+                ("06".into(), start_of("06")),
+                ("07".into(), "ends in the future".into()),
+                ("08".into(), "already exists".into()),
+                ("08".into(), start_of("08")),
+                // The ongoing record blocks everything after its start
+                ("11".into(), start_of("10")),
+            ]
+        );
+
+        let indices = merge
+            .records
+            .iter()
+            .map(|r| (r.i, r.start.format("%H")))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            indices,
+            [(2, "10".into()), (1, "08".into()), (0, "06".into())]
+        );
+        assert_eq!(merge.added.len(), 1);
+        assert_eq!(merge.added[0].i, 0);
+    }
+}
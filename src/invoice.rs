@@ -0,0 +1,431 @@
+use std::collections::BTreeMap;
+
+use ::csv::WriterBuilder;
+
+use crate::card::Card;
+use crate::err::*;
+use crate::project::{Project, Projects};
+use crate::record::Record;
+use crate::round::RoundingOptions;
+use crate::time::Timestamp;
+
+const COLUMNS: [&str; 6] = ["Date", "Project", "Note", "Hours", "Rate", "Amount"];
+
+arg_enum! {
+    #[derive(Clone, Copy, Debug)]
+    pub enum InvoiceFormat {
+        Markdown,
+        Html,
+        Csv,
+    }
+}
+
+/// A line item, or a subtotal or total if it has no date. Hours and amounts are rounded
+/// to hundredths as printed, so that sums add up to the printed lines.
+struct Line {
+    date: Option<String>,
+    project: String,
+    note: String,
+    centihours: i64,
+    rate: Option<f64>,
+    cents: Option<i64>,
+    currency: Option<String>,
+}
+
+impl Line {
+    fn is_sum(&self) -> bool {
+        self.date.is_none()
+    }
+    fn format_money(&self, amount: Option<String>) -> String {
+        match (amount, &self.currency) {
+            (Some(amount), Some(currency)) => format!("{} {}", amount, currency),
+            (Some(amount), None) => amount,
+            (None, _) => String::new(),
+        }
+    }
+    // Cells as printed in Markdown and HTML
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.date.clone().unwrap_or_default(),
+            self.project.clone(),
+            self.note.clone(),
+            format_hundredths(self.centihours),
+            self.format_money(self.rate.map(|rate| format!("{:.2}", rate))),
+            self.format_money(self.cents.map(format_hundredths)),
+        ]
+    }
+}
+
+/// A timesheet of the records of all cards that are billed to the projects of a client
+pub struct Invoice {
+    title: String,
+    lines: Vec<Line>,
+    /// Cards that couldn't be read, along with the reason
+    pub skipped_cards: Vec<(String, Error)>,
+}
+
+impl Invoice {
+    /// Collects the records that start within the range. Records are rounded by the
+    /// rounding of their project, or by the given rounding if their project has none.
+    pub fn build(
+        client: &str,
+        since: Timestamp,
+        until: Timestamp,
+        rounding_opts: &RoundingOptions,
+    ) -> Result<Self> {
+        let projects = Projects::load()?;
+        let client_projects = projects.of_client(client).collect::<BTreeMap<_, _>>();
+        let client = match client_projects.values().find_map(|p| p.client.as_ref()) {
+            Some(client) => client.clone(),
+            None => return Err(ErrorKind::ClientNotFound(client.into()).into()),
+        };
+
+        let mut records_by_project = BTreeMap::new();
+        let mut skipped_cards = Vec::new();
+        for name in Card::list()? {
+            let records = match Card::select(Some(&name))
+                .and_then(|card| card.query(Some(since), Some(until)))
+            {
+                Ok(records) => records,
+                Err(e) => {
+                    skipped_cards.push((name, e));
+                    continue;
+                }
+            };
+            for r in records {
+                let project = match &r.project {
+                    Some(project) if client_projects.contains_key(project.as_str()) => {
+                        project.clone()
+                    }
+                    _ => continue,
+                };
+                // Ongoing records are billed once they are completed
+                if r.start >= since && r.is_terminated() {
+                    records_by_project
+                        .entry(project)
+                        .or_insert_with(Vec::new)
+                        .push(r);
+                }
+            }
+        }
+
+        Ok(Invoice {
+            title: format!("Timesheet for {}, {}", client, since.format("%B %Y")),
+            lines: Invoice::lines(records_by_project, &client_projects, rounding_opts),
+            skipped_cards,
+        })
+    }
+
+    // Lists the records of each project, followed by their subtotal, and ends with the
+    // totals of each currency
+    fn lines(
+        records_by_project: BTreeMap<String, Vec<Record>>,
+        projects: &BTreeMap<&str, &Project>,
+        rounding_opts: &RoundingOptions,
+    ) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut totals = BTreeMap::new();
+        for (name, mut records) in records_by_project {
+            let project = projects[name.as_str()];
+            records.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+
+            let items = records
+                .iter()
+                .map(|r| Invoice::item(r, &name, project, rounding_opts))
+                .collect::<Vec<_>>();
+            let subtotal = Invoice::sum(&items, name.clone(), "Subtotal");
+
+            let total = totals
+                .entry(project.currency.clone())
+                .or_insert_with(Vec::new);
+            total.push(Invoice::sum(&items, String::new(), ""));
+
+            lines.extend(items);
+            lines.push(subtotal);
+        }
+
+        // Amounts in different currencies are totalled separately
+        for subtotals in totals.into_values() {
+            lines.push(Invoice::sum(&subtotals, String::new(), "Total"));
+        }
+
+        lines
+    }
+
+    fn item(r: &Record, name: &str, project: &Project, rounding_opts: &RoundingOptions) -> Line {
+        let duration = r.duration().round(project.rounding(rounding_opts));
+
+        Line {
+            date: Some(r.start.format("%F")),
+            project: name.into(),
+            note: r.note.clone().unwrap_or_default(),
+            centihours: to_hundredths(duration.in_hours()),
+            rate: project.rate,
+            cents: project.amount(&duration).map(to_hundredths),
+            currency: project.currency.clone(),
+        }
+    }
+
+    // Lines must share their currency
+    fn sum(lines: &[Line], project: String, note: &str) -> Line {
+        let cents = lines.iter().filter_map(|l| l.cents).collect::<Vec<_>>();
+
+        Line {
+            date: None,
+            project,
+            note: note.into(),
+            centihours: lines.iter().map(|l| l.centihours).sum(),
+            rate: None,
+            cents: Some(cents.iter().sum()).filter(|_| !cents.is_empty()),
+            currency: lines.first().and_then(|l| l.currency.clone()),
+        }
+    }
+
+    pub fn format(&self, format: InvoiceFormat) -> Result<String> {
+        match format {
+            InvoiceFormat::Markdown => Ok(self.to_markdown()),
+            InvoiceFormat::Html => Ok(self.to_html()),
+            InvoiceFormat::Csv => self.to_csv(),
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut md = format!("# {}\n\n", self.title);
+        md.push_str(&format!("| {} |\n", COLUMNS.join(" | ")));
+        md.push_str("| --- | --- | --- | ---: | ---: | ---: |\n");
+
+        for line in self.lines.iter() {
+            let cells = line
+                .cells()
+                .into_iter()
+                .map(|cell| {
+                    let cell = cell.replace('|', "\\|");
+                    if cell.is_empty() || !line.is_sum() {
+                        cell
+                    } else {
+                        format!("**{}**", cell)
+                    }
+                })
+                .collect::<Vec<_>>();
+            md.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+
+        md
+    }
+
+    fn to_html(&self) -> String {
+        let header = COLUMNS
+            .iter()
+            .map(|column| format!("<th>{}</th>", column))
+            .collect::<String>();
+        let rows = self
+            .lines
+            .iter()
+            .map(|line| {
+                let tag = if line.is_sum() { "th" } else { "td" };
+                let cells = line
+                    .cells()
+                    .iter()
+                    .map(|cell| format!("<{tag}>{}</{tag}>", escape_html(cell), tag = tag))
+                    .collect::<String>();
+
+                format!("      <tr>{}</tr>\n", cells)
+            })
+            .collect::<String>();
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n  <meta charset=\"utf-8\">\n  <title>{title}</title>\n</head>\n<body>\n  <h1>{title}</h1>\n  <table>\n    <thead>\n      <tr>{}</tr>\n    </thead>\n    <tbody>\n{}    </tbody>\n  </table>\n</body>\n</html>\n",
+            header,
+            rows,
+            title = escape_html(&self.title),
+        )
+    }
+
+    // Numbers are kept apart from the currency so that spreadsheets can sum them up
+    fn to_csv(&self) -> Result<String> {
+        let mut writer = WriterBuilder::new().from_writer(vec![]);
+        writer.write_record(COLUMNS.iter().chain(["Currency"].iter()))?;
+
+        for line in self.lines.iter() {
+            writer.write_record(&[
+                line.date.clone().unwrap_or_default(),
+                line.project.clone(),
+                line.note.clone(),
+                format_hundredths(line.centihours),
+                line.rate
+                    .map_or(String::new(), |rate| format!("{:.2}", rate)),
+                line.cents.map_or(String::new(), format_hundredths),
+                line.currency.clone().unwrap_or_default(),
+            ])?;
+        }
+
+        let contents = writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .chain_err(|| "Could not serialize invoice")?;
+
+        String::from_utf8(contents).chain_err(|| "Could not serialize invoice")
+    }
+}
+
+fn to_hundredths(n: f64) -> i64 {
+    (n * 100.0).round() as i64
+}
+
+fn format_hundredths(n: i64) -> String {
+    format!("{}.{:02}", n / 100, n % 100)
+}
+
+fn escape_html(string: &str) -> String {
+    string
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn projects() -> Projects {
+        Projects::parse(
+            "[ACME]\nclient = \"Acme\"\nrate = 100.0\ncurrency = \"EUR\"\n\
+             [Anvils]\nclient = \"Acme\"\nrate = 90.0\ncurrency = \"EUR\"\n\
+             [Rockets]\nclient = \"Acme\"\nrate = 120.0\ncurrency = \"USD\"\n\
+             [Support]\nclient = \"Acme\"\n",
+        )
+        .unwrap()
+    }
+
+    // Returns records of the project from and to the given times of the same day
+    fn records(project: &str, times: &[(&str, &str)]) -> Vec<Record> {
+        let at = |time: &str| Timestamp::try_from(format!("2026-10-01T{}:00+00:00", time).as_str());
+
+        times
+            .iter()
+            .enumerate()
+            .map(|(i, (start, end))| Record {
+                i,
+                start: at(start).unwrap(),
+                end: Some(at(end).unwrap()),
+                note: None,
+                project: Some(project.into()),
+            })
+            .collect()
+    }
+
+    // Returns the project, note, hours, amount and currency of each line
+    fn lines(records: Vec<Vec<Record>>) -> Vec<(String, String, String, String, String)> {
+        let projects = projects();
+        let client_projects = projects.of_client("Acme").collect::<BTreeMap<_, _>>();
+        let records_by_project = records
+            .into_iter()
+            .map(|records| (records[0].project.clone().unwrap(), records))
+            .collect();
+
+        Invoice::lines(records_by_project, &client_projects, &Default::default())
+            .into_iter()
+            .map(|l| {
+                let cents = l.cents.map_or(String::new(), format_hundredths);
+                let currency = l.currency.unwrap_or_default();
+                (
+                    l.project,
+                    l.note,
+                    format_hundredths(l.centihours),
+                    cents,
+                    currency,
+                )
+            })
+            .collect()
+    }
+
+    fn line(
+        project: &str,
+        note: &str,
+        hours: &str,
+        amount: &str,
+        currency: &str,
+    ) -> (String, String, String, String, String) {
+        (
+            project.into(),
+            note.into(),
+            hours.into(),
+            amount.into(),
+            currency.into(),
+        )
+    }
+
+    #[test]
+    fn hundredths() {
+        assert_eq!(to_hundredths(1.0 / 3.0), 33);
+        assert_eq!(to_hundredths(2.0 / 3.0), 67);
+        assert_eq!(to_hundredths(12.345_6), 1235);
+        assert_eq!(format_hundredths(5), "0.05");
+        assert_eq!(format_hundredths(1234), "12.34");
+        assert_eq!(format_hundredths(0), "0.00");
+    }
+
+    #[test]
+    fn sums_add_up_to_the_printed_lines() {
+        // A third of an hour each, which is 33.33 EUR rather than a third of 100 EUR
+        let acme = records(
+            "ACME",
+            &[("08:00", "08:20"), ("09:00", "09:20"), ("10:00", "10:20")],
+        );
+
+        assert_eq!(
+            lines(vec![acme]),
+            [
+                line("ACME", "", "0.33", "33.33", "EUR"),
+                line("ACME", "", "0.33", "33.33", "EUR"),
+                line("ACME", "", "0.33", "33.33", "EUR"),
+                line("ACME", "Subtotal", "0.99", "99.99", "EUR"),
+                line("", "Total", "0.99", "99.99", "EUR"),
+            ]
+        );
+    }
+
+    #[test]
+    fn subtotals_of_each_project() {
+        let acme = records("ACME", &[("08:00", "09:30")]);
+        let anvils = records("Anvils", &[("10:00", "11:00"), ("11:00", "11:15")]);
+        let support = records("Support", &[("12:00", "12:30")]);
+
+        assert_eq!(
+            lines(vec![acme, anvils, support]),
+            [
+                line("ACME", "", "1.50", "150.00", "EUR"),
+                line("ACME", "Subtotal", "1.50", "150.00", "EUR"),
+                line("Anvils", "", "1.00", "90.00", "EUR"),
+                line("Anvils", "", "0.25", "22.50", "EUR"),
+                line("Anvils", "Subtotal", "1.25", "112.50", "EUR"),
+                // Projects without rate aren't billed
+                line("Support", "", "0.50", "", ""),
+                line("Support", "Subtotal", "0.50", "", ""),
+                line("", "Total", "0.50", "", ""),
+                line("", "Total", "2.75", "262.50", "EUR"),
+            ]
+        );
+    }
+
+    #[test]
+    fn totals_of_each_currency() {
+        let acme = records("ACME", &[("08:00", "09:00")]);
+        let rockets = records("Rockets", &[("10:00", "12:00")]);
+
+        assert_eq!(
+            lines(vec![acme, rockets]),
+            [
+                line("ACME", "", "1.00", "100.00", "EUR"),
+                line("ACME", "Subtotal", "1.00", "100.00", "EUR"),
+                line("Rockets", "", "2.00", "240.00", "USD"),
+                line("Rockets", "Subtotal", "2.00", "240.00", "USD"),
+                line("", "Total", "1.00", "100.00", "EUR"),
+                line("", "Total", "2.00", "240.00", "USD"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::card::Card;
+use crate::err::*;
+use crate::file::{self, FileLock};
+use crate::record::Record;
+use crate::time::Timestamp;
+
+const JOURNAL_FILE: &str = "journal.ndjson";
+const JOURNAL_LOCK_FILE: &str = ".journal.lock";
+// Oldest entries are dropped once the journal grows beyond either limit. The journal is
+// then trimmed to half of them, so that it is only rewritten once in a while.
+const JOURNAL_MAX_ENTRIES: usize = 200;
+const JOURNAL_MAX_BYTES: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    In,
+    Out,
+    Switch,
+    Add,
+    Import,
+    Edit,
+    Cancel,
+    Repair,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// A record changed by an operation, identified by its index. A record that didn't
+/// exist before or doesn't exist after the operation is `None`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Change {
+    pub i: usize,
+    pub before: Option<Record>,
+    pub after: Option<Record>,
+}
+
+impl Change {
+    /// Returns the changes between two sets of records, compared by index
+    pub fn between(before: &[Record], after: &[Record]) -> Vec<Change> {
+        let mut changes = BTreeMap::new();
+        for r in before.iter() {
+            changes.insert(r.i, (Some(r), None));
+        }
+        for r in after.iter() {
+            changes.entry(r.i).or_insert((None, None)).1 = Some(r);
+        }
+
+        changes
+            .into_iter()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(i, (before, after))| Change {
+                i,
+                before: before.cloned(),
+                after: after.cloned(),
+            })
+            .collect()
+    }
+
+    pub fn reversed(&self) -> Change {
+        Change {
+            i: self.i,
+            before: self.after.clone(),
+            after: self.before.clone(),
+        }
+    }
+}
+
+/// A card mutation along with the records it changed
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub seq: usize,
+    pub time: Timestamp,
+    pub card: String,
+    pub op: Operation,
+    pub undone: bool,
+    changes: Vec<Change>,
+}
+
+/// The journal of all cards. It stays locked until dropped, so callers that
+/// also lock a card must lock the card first.
+pub struct Journal(
+    PathBuf,
+    Vec<Entry>,
+    // Only held to keep the journal locked
+    #[allow(dead_code)] FileLock,
+);
+
+impl Journal {
+    pub fn open() -> Result<Self> {
+        Journal::open_in(&Card::dir()?)
+    }
+
+    fn open_in(dir: &Path) -> Result<Self> {
+        let lock = FileLock::acquire(&dir.join(JOURNAL_LOCK_FILE))?;
+        let path = dir.join(JOURNAL_FILE);
+
+        if !path.exists() {
+            return Ok(Journal(path, Vec::new(), lock));
+        }
+
+        let entries = fs::read_to_string(&path)
+            .chain_err(|| "Failed to read journal")?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<Vec<Entry>, _>>()
+            .chain_err(|| "Failed to read journal")?;
+
+        Ok(Journal(path, entries, lock))
+    }
+
+    pub fn record(&mut self, card: &str, op: Operation, changes: Vec<Change>) -> Result<()> {
+        let seq = self.1.last().map_or(0, |e| e.seq + 1);
+
+        // A new operation invalidates everything that could have been redone
+        let num_entries = self.1.len();
+        self.1.retain(|e| e.card != card || !e.undone);
+        let is_append_only = self.1.len() == num_entries;
+
+        let entry = Entry {
+            seq,
+            time: Timestamp::now(),
+            card: card.into(),
+            op,
+            undone: false,
+            changes,
+        };
+        let line = serialize_entry(&entry)?;
+        self.1.push(entry);
+
+        let size = fs::metadata(&self.0).map_or(0, |m| m.len() as usize);
+        if is_append_only
+            && size + line.len() <= JOURNAL_MAX_BYTES
+            && self.1.len() <= JOURNAL_MAX_ENTRIES
+        {
+            return self.append(&line);
+        }
+
+        self.trim();
+        self.save()
+    }
+
+    /// Reverts the changes of the most recent operation of the card
+    pub fn undo(&mut self, card: &Card, lock: &FileLock) -> Result<&Entry> {
+        let n = self
+            .1
+            .iter()
+            .rposition(|e| e.card == card.name() && !e.undone)
+            .ok_or(ErrorKind::NothingToUndo)?;
+
+        let reverted = self.1[n]
+            .changes
+            .iter()
+            .map(Change::reversed)
+            .collect::<Vec<_>>();
+        card.apply(lock, self.1[n].op, &reverted)?;
+
+        self.1[n].undone = true;
+        self.save()?;
+
+        Ok(&self.1[n])
+    }
+
+    /// Reapplies the changes of the most recently undone operation of the card
+    pub fn redo(&mut self, card: &Card, lock: &FileLock) -> Result<&Entry> {
+        let n = self
+            .1
+            .iter()
+            .position(|e| e.card == card.name() && e.undone)
+            .ok_or(ErrorKind::NothingToRedo)?;
+
+        card.apply(lock, self.1[n].op, &self.1[n].changes)?;
+
+        self.1[n].undone = false;
+        self.save()?;
+
+        Ok(&self.1[n])
+    }
+
+    /// Returns the operations of a card, most recent first
+    pub fn entries<'a>(&'a self, card: &'a str) -> impl Iterator<Item = &'a Entry> {
+        self.1.iter().rev().filter(move |e| e.card == card)
+    }
+
+    pub fn rename_card(&mut self, card: &str, new_name: &str) -> Result<()> {
+        for e in self.1.iter_mut().filter(|e| e.card == card) {
+            e.card = new_name.into();
+        }
+
+        self.save()
+    }
+
+    pub fn forget_card(&mut self, card: &str) -> Result<()> {
+        self.1.retain(|e| e.card != card);
+
+        self.save()
+    }
+
+    // Drops the oldest entries, but always keeps the newest one
+    fn trim(&mut self) {
+        let mut size = 0;
+        let num_kept = self
+            .1
+            .iter()
+            .rev()
+            .take(JOURNAL_MAX_ENTRIES / 2)
+            .take_while(|e| {
+                size += serialize_entry(e).map_or(0, |line| line.len());
+                size <= JOURNAL_MAX_BYTES / 2
+            })
+            .count()
+            .max(1);
+
+        let num_dropped = self.1.len().saturating_sub(num_kept);
+        self.1.drain(..num_dropped);
+    }
+
+    fn append(&self, line: &str) -> Result<()> {
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.0)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .chain_err(|| "Could not write to journal")
+    }
+
+    fn save(&self) -> Result<()> {
+        let contents = self
+            .1
+            .iter()
+            .map(serialize_entry)
+            .collect::<Result<String>>()?;
+
+        file::write_atomically(&self.0, &contents).chain_err(|| "Could not write to journal")
+    }
+}
+
+// Entries are stored as JSON, one per line
+fn serialize_entry(entry: &Entry) -> Result<String> {
+    serde_json::to_string(entry)
+        .map(|line| line + "\n")
+        .chain_err(|| "Could not serialize journal")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{record, summary, TempDir};
+
+    // Opens a card in the directory, holding the given records
+    fn open_card(dir: &TempDir, records: &[Record]) -> (Card, FileLock) {
+        let card = Card::open(dir.path().join("main.csv")).unwrap();
+        let lock = card.lock().unwrap();
+        let changes = Change::between(&[], records);
+        card.apply(&lock, Operation::Add, &changes).unwrap();
+
+        (card, lock)
+    }
+
+    // Changes the records of the card and journals the changes
+    fn change(journal: &mut Journal, card: &Card, lock: &FileLock, records: &[Record]) {
+        let changes = Change::between(&card.query(None, None).unwrap(), records);
+        card.apply(lock, Operation::Edit, &changes).unwrap();
+        journal
+            .record(card.name(), Operation::Edit, changes)
+            .unwrap();
+    }
+
+    fn notes(card: &Card) -> Vec<(usize, Option<String>, String)> {
+        let mut records = card.query(None, None).unwrap();
+        records.sort_by_key(|r| r.i);
+
+        summary(&records)
+    }
+
+    #[test]
+    fn changes_between_records() {
+        let before = [record(0, 8, Some(9), "a"), record(1, 9, Some(10), "b")];
+        let after = [
+            record(0, 8, Some(9), "a"),
+            record(1, 9, Some(11), "b"),
+            record(2, 11, None, "c"),
+        ];
+
+        let changes = Change::between(&before, &after);
+        let indices = changes.iter().map(|c| c.i).collect::<Vec<_>>();
+        assert_eq!(indices, vec![1, 2]);
+        assert_eq!(changes[0].before.as_ref(), Some(&before[1]));
+        assert_eq!(changes[0].after.as_ref(), Some(&after[1]));
+        assert_eq!(changes[1].before, None);
+
+        let reversed = changes[1].reversed();
+        assert_eq!(reversed.before.as_ref(), Some(&after[2]));
+        assert_eq!(reversed.after, None);
+    }
+
+    #[test]
+    fn undoing_and_redoing_operations() {
+        let dir = TempDir::new();
+        let (card, lock) = open_card(&dir, &[record(0, 8, Some(9), "a")]);
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+
+        change(
+            &mut journal,
+            &card,
+            &lock,
+            &[record(0, 8, Some(10), "a"), record(1, 10, None, "b")],
+        );
+        let changed = notes(&card);
+
+        journal.undo(&card, &lock).unwrap();
+        assert_eq!(notes(&card), vec![(0, Some("09".into()), "a".into())]);
+        assert!(matches!(
+            journal.undo(&card, &lock),
+            Err(Error(ErrorKind::NothingToUndo, _))
+        ));
+
+        journal.redo(&card, &lock).unwrap();
+        assert_eq!(notes(&card), changed);
+        assert!(matches!(
+            journal.redo(&card, &lock),
+            Err(Error(ErrorKind::NothingToRedo, _))
+        ));
+    }
+
+    #[test]
+    fn undone_operations_are_kept_across_openings() {
+        let dir = TempDir::new();
+        let (card, lock) = open_card(&dir, &[record(0, 8, Some(9), "a")]);
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+        change(&mut journal, &card, &lock, &[record(0, 8, Some(10), "a")]);
+        journal.undo(&card, &lock).unwrap();
+        drop(journal);
+
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+        assert!(journal.entries("main").all(|e| e.undone));
+        journal.redo(&card, &lock).unwrap();
+        assert_eq!(notes(&card), vec![(0, Some("10".into()), "a".into())]);
+    }
+
+    #[test]
+    fn new_operations_drop_undone_ones() {
+        let dir = TempDir::new();
+        let (card, lock) = open_card(&dir, &[record(0, 8, Some(9), "a")]);
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+
+        change(&mut journal, &card, &lock, &[record(0, 8, Some(10), "a")]);
+        journal.undo(&card, &lock).unwrap();
+        change(&mut journal, &card, &lock, &[record(0, 8, Some(11), "a")]);
+
+        assert_eq!(journal.entries("main").count(), 1);
+        assert!(matches!(
+            journal.redo(&card, &lock),
+            Err(Error(ErrorKind::NothingToRedo, _))
+        ));
+    }
+
+    #[test]
+    fn applying_changes_to_modified_records() {
+        let dir = TempDir::new();
+        let (card, lock) = open_card(&dir, &[record(0, 8, Some(9), "a")]);
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+        change(&mut journal, &card, &lock, &[record(0, 8, Some(10), "a")]);
+
+        // Modified by hand
+        let changes = Change::between(
+            &card.query(None, None).unwrap(),
+            &[record(0, 8, Some(10), "edited")],
+        );
+        card.apply(&lock, Operation::Edit, &changes).unwrap();
+
+        assert!(matches!(
+            journal.undo(&card, &lock),
+            Err(Error(ErrorKind::CardModifiedSinceOperation(_), _))
+        ));
+        assert_eq!(notes(&card), vec![(0, Some("10".into()), "edited".into())]);
+    }
+
+    // Returns the sequence numbers of the entries saved in the directory, newest first
+    fn saved_entries(dir: &TempDir) -> Vec<usize> {
+        let journal = Journal::open_in(dir.path()).unwrap();
+
+        journal.entries("main").map(|e| e.seq).collect()
+    }
+
+    fn punch_in(journal: &mut Journal, i: usize, note: &str) {
+        let changes = Change::between(&[], &[record(i, 8, None, note)]);
+        journal.record("main", Operation::In, changes).unwrap();
+    }
+
+    #[test]
+    fn trimming_by_the_number_of_entries() {
+        let dir = TempDir::new();
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+        for i in 0..=JOURNAL_MAX_ENTRIES {
+            punch_in(&mut journal, i, "");
+        }
+        drop(journal);
+
+        let seqs = saved_entries(&dir);
+        assert_eq!(seqs.len(), JOURNAL_MAX_ENTRIES / 2);
+        assert_eq!(seqs[0], JOURNAL_MAX_ENTRIES);
+    }
+
+    #[test]
+    fn trimming_by_size() {
+        let dir = TempDir::new();
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+        let note = "x".repeat(JOURNAL_MAX_BYTES * 2 / 5);
+        punch_in(&mut journal, 0, &note);
+        punch_in(&mut journal, 1, &note);
+        drop(journal);
+        assert_eq!(saved_entries(&dir), vec![1, 0]);
+
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+        punch_in(&mut journal, 2, &note);
+        drop(journal);
+        assert_eq!(saved_entries(&dir), vec![2]);
+
+        // The newest entry is kept even if it exceeds the limit on its own
+        let mut journal = Journal::open_in(dir.path()).unwrap();
+        punch_in(&mut journal, 3, &"x".repeat(JOURNAL_MAX_BYTES));
+        drop(journal);
+        assert_eq!(saved_entries(&dir), vec![3]);
+    }
+}
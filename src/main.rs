@@ -1,3 +1,6 @@
+// error_chain! expands recursively for every error kind
+#![recursion_limit = "256"]
+
 #[macro_use]
 extern crate clap;
 #[macro_use]
@@ -7,19 +10,40 @@ mod bucket;
 mod card;
 mod cli;
 mod cmd;
+mod config;
+mod doctor;
 mod err;
+mod export;
+mod file;
 mod format;
+mod import;
+mod invoice;
+mod journal;
+mod project;
+mod prompt;
 mod record;
 mod round;
+mod store;
+#[cfg(test)]
+mod testing;
 mod time;
 
 use std::convert::TryFrom;
 use std::process;
 
+use clap::ArgMatches;
+
+use card::Card;
+use config::Config;
 use err::*;
-use format::{CardFormattingOptions, RecordFormattingOptions};
+use export::ExportFormat;
+use format::{CardFormattingOptions, RecordFormattingOptions, ShowFormat};
+use import::ImportFormat;
+use invoice::InvoiceFormat;
+use project::Projects;
 use round::RoundingOptions;
-use time::Interval;
+use store::Backend;
+use time::{Calendar, Duration, Interval, Period, Span, Timestamp, Zone};
 
 fn main() {
     match run() {
@@ -35,44 +59,222 @@ fn run() -> Result<()> {
     let matches = cli::get_matches();
 
     match matches.subcommand() {
-        ("status", _) => {
-            cmd::status::run()
+        ("status", Some(status_matches)) => {
+            let card = Card::select(status_matches.value_of("card"))?;
+            cmd::status::run(card)
         }
         ("in", Some(in_matches)) => {
+            let card = Card::select(in_matches.value_of("card"))?;
+            let timestamp = punch_timestamp(in_matches, &card)?;
             let note = in_matches.value_of("note");
-            cmd::inn::run(note)
+            let project = in_matches.value_of("project");
+            cmd::inn::run(card, timestamp, note, project)
         }
         ("out", Some(out_matches)) => {
+            let card = Card::select(out_matches.value_of("card"))?;
+            let timestamp = punch_timestamp(out_matches, &card)?;
             let note = out_matches.value_of("note");
-            cmd::out::run(note)
+            cmd::out::run(card, timestamp, note)
+        }
+        ("switch", Some(switch_matches)) => {
+            let card = Card::select(switch_matches.value_of("card"))?;
+            let timestamp = punch_timestamp(switch_matches, &card)?;
+            let note = switch_matches.value_of("note");
+            let project = switch_matches.value_of("project");
+            cmd::switch::run(card, timestamp, note, project)
+        }
+        ("cancel", Some(cancel_matches)) => {
+            let card = Card::select(cancel_matches.value_of("card"))?;
+            cmd::cancel::run(card, cancel_matches.is_present("yes"))
         }
+        ("add", Some(add_matches)) => {
+            let card = Card::select(add_matches.value_of("card"))?;
+            let zone = Config::load()?.zone_of(&card)?;
+            let start = Timestamp::parse_in(add_matches.value_of("start").unwrap(), zone)?;
+            let end = Timestamp::parse_in(add_matches.value_of("end").unwrap(), zone)?;
+            let note = add_matches.value_of("note");
+            let project = add_matches.value_of("project");
+            cmd::add::run(card, start, end, note, project)
+        }
+        ("card", Some(card_matches)) => match card_matches.subcommand() {
+            ("list", _) => cmd::card::list(),
+            ("new", Some(new_matches)) => {
+                let name = new_matches.value_of("name").unwrap();
+                let backend =
+                    value_t!(new_matches.value_of("backend"), Backend).unwrap_or_else(|e| e.exit());
+                cmd::card::new(name, backend, new_matches.is_present("switch"))
+            }
+            ("switch", Some(switch_matches)) => {
+                let name = switch_matches.value_of("name").unwrap();
+                cmd::card::switch(name)
+            }
+            ("rename", Some(rename_matches)) => {
+                let name = rename_matches.value_of("name").unwrap();
+                let new_name = rename_matches.value_of("new_name").unwrap();
+                cmd::card::rename(name, new_name)
+            }
+            ("rm", Some(rm_matches)) => {
+                let name = rm_matches.value_of("name").unwrap();
+                cmd::card::remove(name, rm_matches.is_present("force"))
+            }
+            _ => unreachable!(),
+        },
         ("show", Some(show_matches)) => {
-            // using value_t! to get typed Interval instead of a string
-            let interval =
-                value_t!(show_matches.value_of("interval"), Interval).unwrap_or_else(|e| e.exit());
+            let card = Card::select(show_matches.value_of("card"))?;
+            let config = Config::load()?;
+            let zone = match show_matches.value_of("tz") {
+                _ if show_matches.is_present("recorded_offset") => Zone::Recorded,
+                Some(name) => Zone::try_from(name)?,
+                None => config.zone_of(&card)?,
+            };
+            let calendar = Calendar {
+                zone,
+                ..config.calendar
+            };
+            let interval = show_matches.value_of("interval").unwrap();
+            // Shortcuts like "last-month" imply a range and group by a shorter interval
+            let (span, since, until) = match Period::try_from(interval) {
+                Ok(period) => {
+                    let (since, until) = period.bounds(&calendar);
+                    (Span::from(period.2), Some(since), until)
+                }
+                Err(_) => (Span::try_from(interval)?, None, None),
+            };
+            // Only calendar periods split records by default, records rarely span hours
+            let split = match span.interval {
+                _ if show_matches.is_present("split") => true,
+                _ if show_matches.is_present("no_split") => false,
+                Interval::Second | Interval::Minute | Interval::Hour => false,
+                _ => true,
+            };
+            let since = match show_matches.value_of("last") {
+                Some(_) => {
+                    let n =
+                        value_t!(show_matches.value_of("last"), i32).unwrap_or_else(|e| e.exit());
+                    Some(Timestamp::now().start_of_span(span, 1 - n, &calendar))
+                }
+                None => show_matches
+                    .value_of("since")
+                    .map(|since| Timestamp::parse_in(since, zone))
+                    .transpose()?
+                    .or(since),
+            };
+            let until = show_matches
+                .value_of("until")
+                .map(|until| Timestamp::parse_in(until, zone))
+                .transpose()?
+                .or(until);
             let precise = show_matches.is_present("precise");
             let timezone = show_matches.is_present("timezone");
             let rounding = show_matches
                 .value_of("rounding")
                 .map_or(Ok(Default::default()), RoundingOptions::try_from)?;
+            let tag = show_matches.value_of("tag").map(String::from);
+            let format =
+                value_t!(show_matches.value_of("format"), ShowFormat).unwrap_or_else(|e| e.exit());
 
             let opts = CardFormattingOptions {
-                interval,
+                span,
+                format,
+                tag,
+                since,
+                until,
+                split,
+                calendar,
                 record_opts: RecordFormattingOptions {
                     rounding_opts: rounding,
+                    projects: Projects::load()?,
                     precise,
                     timezone,
                     ..Default::default()
                 },
             };
 
-            cmd::show::run(opts)
+            cmd::show::run(card, opts)
+        }
+        ("export", Some(export_matches)) => {
+            let card = Card::select(export_matches.value_of("card"))?;
+            let format = value_t!(export_matches.value_of("format"), ExportFormat)
+                .unwrap_or_else(|e| e.exit());
+            let zone = Config::load()?.zone_of(&card)?;
+            let since = export_matches
+                .value_of("since")
+                .map(|since| Timestamp::parse_in(since, zone))
+                .transpose()?;
+            let until = export_matches
+                .value_of("until")
+                .map(|until| Timestamp::parse_in(until, zone))
+                .transpose()?;
+            let rounding = export_matches
+                .value_of("rounding")
+                .map_or(Ok(Default::default()), RoundingOptions::try_from)?;
+
+            let include_ongoing = export_matches.is_present("ongoing");
+
+            cmd::export::run(
+                card,
+                format,
+                since,
+                until,
+                Projects::load()?,
+                rounding,
+                include_ongoing,
+            )
+        }
+        ("import", Some(import_matches)) => {
+            let card = Card::select(import_matches.value_of("card"))?;
+            let format = value_t!(import_matches.value_of("from"), ImportFormat)
+                .unwrap_or_else(|e| e.exit());
+            let path = import_matches.value_of("file").unwrap();
+            let dry_run = import_matches.is_present("dry_run");
+
+            cmd::import::run(card, format, path, dry_run)
+        }
+        ("invoice", Some(invoice_matches)) => {
+            let client = invoice_matches.value_of("client").unwrap();
+            let month = invoice_matches.value_of("month").unwrap();
+            let format = value_t!(invoice_matches.value_of("format"), InvoiceFormat)
+                .unwrap_or_else(|e| e.exit());
+            let rounding = invoice_matches
+                .value_of("rounding")
+                .map_or(Ok(Default::default()), RoundingOptions::try_from)?;
+
+            cmd::invoice::run(client, month, format, rounding)
         }
-        ("edit", _) => {
-            cmd::edit::run()?;
-            Ok(())
+        ("doctor", Some(doctor_matches)) => {
+            let card = Card::select(doctor_matches.value_of("card"))?;
+            cmd::doctor::run(card, doctor_matches.is_present("fix"))
+        }
+        ("undo", Some(undo_matches)) => {
+            let card = Card::select(undo_matches.value_of("card"))?;
+            cmd::undo::run(card)
+        }
+        ("redo", Some(redo_matches)) => {
+            let card = Card::select(redo_matches.value_of("card"))?;
+            cmd::redo::run(card)
+        }
+        ("log", Some(log_matches)) => {
+            let card = Card::select(log_matches.value_of("card"))?;
+            let limit = value_t!(log_matches.value_of("limit"), usize).unwrap_or_else(|e| e.exit());
+            cmd::log::run(card, limit)
+        }
+        ("edit", Some(edit_matches)) => {
+            let card = Card::select(edit_matches.value_of("card"))?;
+            cmd::edit::run(card)
         }
         // clap takes care of unmatched subcommands
         _ => unreachable!(),
     }
 }
+
+// Resolves the --at and --ago arguments of a punch, defaulting to now. Times of day are
+// read in the zone of the card.
+fn punch_timestamp(matches: &ArgMatches, card: &Card) -> Result<Timestamp> {
+    if let Some(at) = matches.value_of("at") {
+        Timestamp::parse_in(at, Config::load()?.zone_of(card)?)
+    } else if let Some(ago) = matches.value_of("ago") {
+        Ok(Timestamp::now() - Duration::try_from(ago)?)
+    } else {
+        Ok(Timestamp::now())
+    }
+}
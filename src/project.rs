@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+use serde::Deserialize;
+
+use crate::card::Card;
+use crate::err::*;
+use crate::record::Record;
+use crate::round::RoundingOptions;
+use crate::time::Duration;
+
+const PROJECTS_FILE: &str = "projects.toml";
+
+/// A project that records can be billed to
+#[derive(Debug, Deserialize)]
+pub struct Project {
+    pub client: Option<String>,
+    /// Hourly rate, records of projects without a rate are not billable
+    pub rate: Option<f64>,
+    pub currency: Option<String>,
+    /// Replaces the rounding passed on the command line for records of this project
+    pub rounding: Option<RoundingOptions>,
+}
+
+/// The registry of all projects in `~/.punch/projects.toml`, one table per project
+#[derive(Debug, Default)]
+pub struct Projects(BTreeMap<String, Project>);
+
+impl Projects {
+    /// Reads the registry. Without a registry file, there are no projects.
+    pub fn load() -> Result<Self> {
+        let path = Card::dir()?.join(PROJECTS_FILE);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Projects::default()),
+            Err(e) => return Err(e).chain_err(|| "Failed to read projects"),
+        };
+
+        Projects::parse(&contents)
+            .chain_err(|| ErrorKind::InvalidProjects(path.display().to_string()))
+    }
+
+    /// Reads a registry from the contents of a registry file
+    pub fn parse(contents: &str) -> std::result::Result<Self, toml::de::Error> {
+        toml::from_str(contents).map(Projects)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Project> {
+        self.0.get(name)
+    }
+
+    /// Returns the projects of a client, matching the client name case-insensitively
+    pub fn of_client<'a>(
+        &'a self,
+        client: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a Project)> {
+        self.0
+            .iter()
+            .filter(move |(_, p)| {
+                p.client
+                    .as_ref()
+                    .is_some_and(|c| c.eq_ignore_ascii_case(client))
+            })
+            .map(|(name, p)| (name.as_str(), p))
+    }
+
+    /// Returns the project to bill a record to: the given project, which must be
+    /// registered, or else the first `+project` of the note that is registered
+    pub fn resolve(&self, project: Option<&str>, note: Option<&str>) -> Result<Option<String>> {
+        if let Some(name) = project {
+            self.check(name)?;
+            return Ok(Some(name.into()));
+        }
+
+        let tags = note.map(Record::tags_of).unwrap_or_default();
+        let project = tags
+            .iter()
+            .filter_map(|tag| tag.strip_prefix('+'))
+            .find_map(|name| self.0.keys().find(|p| p.eq_ignore_ascii_case(name)));
+
+        Ok(project.cloned())
+    }
+
+    /// Fails unless the project is registered
+    pub fn check(&self, name: &str) -> Result<()> {
+        match self.get(name) {
+            Some(_) => Ok(()),
+            None => Err(ErrorKind::ProjectNotFound(name.into()).into()),
+        }
+    }
+}
+
+impl Project {
+    pub fn rounding<'a>(&'a self, default: &'a RoundingOptions) -> &'a RoundingOptions {
+        self.rounding.as_ref().unwrap_or(default)
+    }
+
+    /// Returns the amount to bill for the duration, if the project has a rate
+    pub fn amount(&self, duration: &Duration) -> Option<f64> {
+        Some(self.rate? * duration.in_hours())
+    }
+
+    /// Formats the amount to bill for the duration, if the project has a rate
+    pub fn format_amount(&self, duration: &Duration) -> Option<String> {
+        Some(self.format_money(self.amount(duration)?))
+    }
+
+    pub fn format_money(&self, amount: f64) -> String {
+        match &self.currency {
+            Some(currency) => format!("{:.2} {}", amount, currency),
+            None => format!("{:.2}", amount),
+        }
+    }
+}
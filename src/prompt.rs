@@ -0,0 +1,14 @@
+use std::io::{self, Write};
+
+use crate::err::*;
+
+/// Asks a yes/no question on the terminal. Anything but "y" or "yes" counts as no.
+pub fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
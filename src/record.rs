@@ -4,9 +4,11 @@ use colored::*;
 use serde::{Deserialize, Serialize};
 
 use crate::format::RecordFormattingOptions;
-use crate::time::{Duration, Interval, Timestamp};
+use crate::project::Projects;
+use crate::round::RoundingOptions;
+use crate::time::{Calendar, Duration, Span, Timestamp, Zone};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Record {
     pub i: usize,
     pub start: Timestamp,
@@ -14,17 +16,144 @@ pub struct Record {
     pub end: Option<Timestamp>,
     #[serde(default)]
     pub note: Option<String>,
+    #[serde(default)]
+    pub project: Option<String>,
 }
 
 impl Record {
+    /// Names of the CSV columns in the order they are stored in
+    pub const FIELDS: [&'static str; 5] = ["i", "start", "end", "note", "project"];
+
     pub fn duration(&self) -> Duration {
         Duration::of_record(self)
     }
+    /// Whether the record lies at least partly within the given range. Ongoing records are open-ended.
+    pub fn overlaps(&self, since: Option<Timestamp>, until: Option<Timestamp>) -> bool {
+        let ends_after_since = match (since, self.end) {
+            (Some(since), Some(end)) => end > since,
+            _ => true,
+        };
+        let starts_before_until = until.is_none_or(|until| self.start < until);
+
+        ends_after_since && starts_before_until
+    }
+    /// Returns the first of the records that overlaps with the time from start to end.
+    /// Ongoing records block everything after their start.
+    pub fn find_overlap<'a>(
+        records: impl IntoIterator<Item = &'a Record>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Option<&'a Record> {
+        let now = Timestamp::now();
+
+        records
+            .into_iter()
+            .find(|r| start < r.end.unwrap_or(now) && r.start < end)
+    }
+    /// Orders the records newest first and indexes them chronologically, from 0 for the
+    /// oldest record
+    pub fn index_chronologically(records: &mut [Record]) {
+        records.sort_by(|a, b| b.start.partial_cmp(&a.start).unwrap());
+
+        let num_records = records.len();
+        for (n, r) in records.iter_mut().enumerate() {
+            r.i = num_records - 1 - n;
+        }
+    }
+    /// Converts start and end to the zone, see `Timestamp::in_zone`
+    pub fn in_zone(self, zone: Zone) -> Record {
+        Record {
+            start: self.start.in_zone(zone),
+            end: self.end.map(|end| end.in_zone(zone)),
+            ..self
+        }
+    }
+    /// Splits the record at the boundaries of the spans it overlaps, e.g. at midnight for
+    /// days. Each part keeps the index of the record, and the last part of an ongoing
+    /// record stays ongoing.
+    pub fn split_at_boundaries(&self, span: Span, calendar: &Calendar) -> Vec<Record> {
+        let end = self.end.unwrap_or_else(Timestamp::now);
+
+        let mut parts = Vec::new();
+        let mut start = self.start;
+        loop {
+            let boundary = start.start_of_span(span, 1, calendar);
+            if boundary >= end {
+                parts.push(Record {
+                    start,
+                    ..self.clone()
+                });
+                return parts;
+            }
+
+            parts.push(Record {
+                start,
+                end: Some(boundary),
+                ..self.clone()
+            });
+            start = boundary;
+        }
+    }
+    /// Returns the `#tag` and `+project` tokens of the note, including their sigil. The
+    /// project of the record is a `+project` tag as well.
+    pub fn tags(&self) -> Vec<String> {
+        let note = self.note.as_deref().unwrap_or_default();
+        let mut tags = Record::tags_of(note)
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        if let Some(project) = &self.project {
+            let tag = format!("+{}", project);
+            if !tags.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+                tags.push(tag);
+            }
+        }
+
+        tags
+    }
+    /// Returns the `#tag` and `+project` tokens of a note, including their sigil
+    pub fn tags_of(note: &str) -> Vec<&str> {
+        // Notes of punch in and punch out are joined with `;`
+        note.split(|c: char| c.is_whitespace() || c == ';')
+            .filter_map(|token| {
+                let name = token.strip_prefix(&['#', '+'][..])?;
+                let len = name
+                    .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                    .unwrap_or(name.len());
+
+                Some(&token[..len + 1]).filter(|_| len > 0)
+            })
+            .collect()
+    }
+    /// Whether the note has the given tag. Tags without sigil match both `#tag` and `+tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags()
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(tag) || t[1..].eq_ignore_ascii_case(tag))
+    }
     pub fn is_terminated(&self) -> bool {
         self.end.is_some() && self.start <= self.end.unwrap()
     }
-    pub fn bucket_key(&self, interval: Interval) -> u32 {
-        self.start.floor_to_interval_units(interval)
+    pub fn bucket_key(&self, span: Span, calendar: &Calendar) -> u32 {
+        // Spans of several units are keyed by their first unit
+        self.start
+            .start_of_span(span, 0, calendar)
+            .floor_to_interval_units(span.interval, calendar)
+    }
+    /// Returns the rounding of the project of the record, if it has one
+    pub fn rounding_opts<'a>(&self, opt: &'a RecordFormattingOptions) -> &'a RoundingOptions {
+        self.rounding_in(&opt.projects, &opt.rounding_opts)
+    }
+    /// Returns the rounding of the project of the record in the registry, or the default
+    pub fn rounding_in<'a>(
+        &self,
+        projects: &'a Projects,
+        default: &'a RoundingOptions,
+    ) -> &'a RoundingOptions {
+        let project = self.project.as_deref().and_then(|p| projects.get(p));
+
+        project.map_or(default, |p| p.rounding(default))
     }
     pub fn format_with(&self, opt: &RecordFormattingOptions) -> String {
         let pad_index = opt.align_with_n_records.to_string().len();
@@ -37,21 +166,33 @@ impl Record {
         let start = self.start.format_with(opt);
         let end = (self.end).map_or("ongoing...".to_string(), |date| date.format_with(opt));
 
-        let duration = self.duration().round(&opt.rounding_opts);
+        let project = self.project.as_deref().and_then(|p| opt.projects.get(p));
+        let rounding_opts = self.rounding_opts(opt);
+        let duration = self.duration().round(rounding_opts);
 
-        let duration = format!("({})", duration.format(&opt.rounding_opts).bright_green());
+        // Projects that were removed from the registry are still named
+        let project = match (
+            &self.project,
+            project.and_then(|p| p.format_amount(&duration)),
+        ) {
+            (Some(name), Some(amount)) => format!("{} {} ", name.cyan(), amount.bright_yellow()),
+            (Some(name), None) => format!("{} ", name.cyan()),
+            (None, _) => String::new(),
+        };
+        let duration = format!("({})", duration.format(rounding_opts).bright_green());
         let note = match &self.note {
             Some(n) => n.dimmed().to_string(),
             None => String::new(),
         };
 
         format!(
-            "{:0>pad_index$}: {} {}  {:<pad_end$} {:<20} {}",
+            "{:0>pad_index$}: {} {}  {:<pad_end$} {:<20} {}{}",
             self.i.to_string().dimmed(),
             start,
             "⟶".dimmed(),
             end,
             duration,
+            project,
             note,
             pad_index = pad_index,
             pad_end = pad_end,
@@ -66,6 +207,7 @@ impl From<(Timestamp, usize, Option<String>)> for Record {
             start: timestamp,
             end: None,
             note,
+            project: None,
         }
     }
 }
@@ -75,3 +217,177 @@ impl fmt::Display for Record {
         write!(f, "{}", &self.format_with(&Default::default()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::testing::record;
+    use crate::time::{Interval, WeekStart};
+
+    fn ts(string: &str) -> Timestamp {
+        Timestamp::try_from(string).unwrap()
+    }
+
+    fn calendar_in(zone: &str) -> Calendar {
+        Calendar {
+            zone: Zone::try_from(zone).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    // Returns the start and end of each part
+    fn bounds(parts: &[Record]) -> Vec<(Timestamp, Option<Timestamp>)> {
+        parts.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn splitting_at_midnight() {
+        let r = Record {
+            start: ts("2026-10-01T22:00:00+00:00"),
+            end: Some(ts("2026-10-03T02:00:00+00:00")),
+            ..record(7, 0, None, "a")
+        };
+        let parts = r.split_at_boundaries(Span::from(Interval::Day), &calendar_in("UTC"));
+
+        assert_eq!(
+            bounds(&parts),
+            [
+                (
+                    ts("2026-10-01T22:00:00+00:00"),
+                    Some(ts("2026-10-02T00:00:00+00:00"))
+                ),
+                (
+                    ts("2026-10-02T00:00:00+00:00"),
+                    Some(ts("2026-10-03T00:00:00+00:00"))
+                ),
+                (
+                    ts("2026-10-03T00:00:00+00:00"),
+                    Some(ts("2026-10-03T02:00:00+00:00"))
+                )
+            ]
+        );
+        assert!(parts
+            .iter()
+            .all(|part| part.i == 7 && part.note.as_deref() == Some("a")));
+
+        // Records within a day are kept whole
+        let r = record(7, 8, Some(9), "a");
+        assert_eq!(
+            r.split_at_boundaries(Span::from(Interval::Day), &calendar_in("UTC")),
+            [r]
+        );
+    }
+
+    #[test]
+    fn splitting_at_week_boundaries() {
+        // From Sunday evening to Monday morning
+        let r = Record {
+            start: ts("2026-10-04T20:00:00+00:00"),
+            end: Some(ts("2026-10-05T04:00:00+00:00")),
+            ..record(3, 0, None, "")
+        };
+        let week = Span::from(Interval::Week);
+
+        assert_eq!(
+            bounds(&r.split_at_boundaries(week, &calendar_in("UTC"))),
+            [
+                (
+                    ts("2026-10-04T20:00:00+00:00"),
+                    Some(ts("2026-10-05T00:00:00+00:00"))
+                ),
+                (
+                    ts("2026-10-05T00:00:00+00:00"),
+                    Some(ts("2026-10-05T04:00:00+00:00"))
+                )
+            ]
+        );
+
+        let calendar = Calendar {
+            week_start: WeekStart::Sunday,
+            ..calendar_in("UTC")
+        };
+        assert_eq!(r.split_at_boundaries(week, &calendar), [r]);
+    }
+
+    #[test]
+    fn splitting_ongoing_records() {
+        let r = Record {
+            start: Timestamp::now() - Duration::try_from("50h").unwrap(),
+            ..record(4, 0, None, "")
+        };
+        let parts = r.split_at_boundaries(Span::from(Interval::Day), &calendar_in("UTC"));
+
+        assert!(parts.len() >= 3);
+        let (last, completed) = parts.split_last().unwrap();
+        assert_eq!(last.end, None);
+        assert!(completed.iter().all(|part| part.end.is_some()));
+        assert!(parts
+            .windows(2)
+            .all(|pair| pair[0].end == Some(pair[1].start)));
+        assert!(parts.iter().all(|part| part.i == 4));
+    }
+
+    #[test]
+    fn splitting_on_days_with_a_dst_transition() {
+        // Clocks in Berlin go back from 03:00 to 02:00 on 2026-10-25
+        let r = Record {
+            start: ts("2026-10-24T22:00:00+02:00"),
+            end: Some(ts("2026-10-26T01:00:00+01:00")),
+            ..record(0, 0, None, "")
+        };
+        let parts = r.split_at_boundaries(Span::from(Interval::Day), &calendar_in("Europe/Berlin"));
+
+        assert_eq!(
+            bounds(&parts),
+            [
+                (
+                    ts("2026-10-24T22:00:00+02:00"),
+                    Some(ts("2026-10-25T00:00:00+02:00"))
+                ),
+                (
+                    ts("2026-10-25T00:00:00+02:00"),
+                    Some(ts("2026-10-26T00:00:00+01:00"))
+                ),
+                (
+                    ts("2026-10-26T00:00:00+01:00"),
+                    Some(ts("2026-10-26T01:00:00+01:00"))
+                )
+            ]
+        );
+        assert_eq!(parts[1].duration().in_seconds(), 25 * 3600);
+    }
+
+    #[test]
+    fn tags_of_notes() {
+        // Notes of punch in and punch out are joined with `;`
+        assert_eq!(
+            Record::tags_of("standup #meeting;+acme"),
+            ["#meeting", "+acme"]
+        );
+        assert_eq!(
+            Record::tags_of("#review, #bug-fix. #ops_1)"),
+            ["#review", "#bug-fix", "#ops_1"]
+        );
+        assert_eq!(Record::tags_of("# heading + plus #"), Vec::<&str>::new());
+        assert_eq!(Record::tags_of("issue#42 a+b"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn matching_tags() {
+        let r = Record {
+            project: Some("Acme".into()),
+            ..record(0, 8, Some(9), "fixing #Bugs, again;#ops")
+        };
+
+        assert!(r.has_tag("#bugs"));
+        assert!(r.has_tag("bugs"));
+        assert!(r.has_tag("ops"));
+        assert!(r.has_tag("+acme"));
+        assert!(r.has_tag("acme"));
+        assert!(!r.has_tag("+bugs"));
+        assert!(!r.has_tag("#acme"));
+        assert!(!r.has_tag("again"));
+    }
+}
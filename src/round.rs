@@ -1,5 +1,7 @@
 use std::convert::TryFrom;
 
+use serde::de::{self, Deserialize, Deserializer};
+
 use crate::err::*;
 use crate::time::Interval;
 
@@ -88,6 +90,17 @@ impl TryFrom<&str> for RoundingOptions {
     }
 }
 
+impl<'de> Deserialize<'de> for RoundingOptions {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let string = String::deserialize(deserializer)?;
+
+        RoundingOptions::try_from(string.as_str()).map_err(de::Error::custom)
+    }
+}
+
 impl RoundingOptions {
     // This is a utility for clap::Arg::validator used in cli.rs
     pub fn validate_str(input: String) -> std::result::Result<(), String> {
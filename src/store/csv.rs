@@ -0,0 +1,630 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use ::csv::{Reader, ReaderBuilder, StringRecord, WriterBuilder};
+
+use super::CardStore;
+use crate::doctor;
+use crate::err::*;
+use crate::file;
+use crate::record::Record;
+use crate::time::Timestamp;
+
+// Bumped whenever the layout of card files changes, along with a migration below
+const VERSION: usize = 3;
+// The metadata line precedes the header row and is read as a comment
+const METADATA_PREFIX: &str = "# punch card v";
+
+// Superseded rows are dropped whenever appending makes the card cross a multiple of this
+// size, so that punches only rarely rewrite the card
+const COMPACTION_INTERVAL: u64 = 256 * 1024;
+/// The reason given for rows that end within a quoted field
+pub const UNTERMINATED: &str = "quoted field is not terminated, the row may have been cut off";
+// The end of the card is read in chunks of this size to find its last row
+const TAIL_CHUNK_SIZE: u64 = 4096;
+
+/// Stores records in a CSV file that is only ever appended to. The file starts with a
+/// metadata line and a header row. Records are ordered oldest first. Punching out appends
+/// the record again, which supersedes the row of the ongoing record.
+pub struct CsvStore(PathBuf);
+
+impl CsvStore {
+    pub fn open(path: PathBuf) -> Result<Self> {
+        // Assumes that the directory already exists
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .chain_err(|| "Failed to create card file")?;
+
+        if file.metadata()?.len() == 0 {
+            file::write_atomically(&path, &preamble())
+                .chain_err(|| "Failed to create card file")?;
+        }
+
+        Ok(CsvStore(path))
+    }
+
+    // Appends the records and returns the size of the card before and after
+    fn append(&self, records: &[Record]) -> Result<(u64, u64)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.0)
+            .chain_err(|| "Failed to open card file")?;
+        let size = file.metadata()?.len();
+
+        // The last row may lack a line break if the card was edited by hand
+        let mut rows = String::new();
+        if size > 0 {
+            let mut last_byte = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last_byte)?;
+            if last_byte[0] != b'\n' {
+                rows.push('\n');
+            }
+        }
+        rows.push_str(&serialize_rows(records)?);
+
+        file.write_all(rows.as_bytes())
+            .and_then(|_| file.sync_all())
+            .chain_err(|| "Could not write to card file")?;
+
+        Ok((size, size + rows.len() as u64))
+    }
+
+    // Reads the rows at the start of the card up to the header row
+    fn header(&self) -> Result<StringRecord> {
+        let file = File::open(&self.0).chain_err(|| "Failed to read card file")?;
+        let first_row = reader_builder().from_reader(file).records().next();
+
+        match first_row {
+            Some(Ok(row)) if is_header(&row) => Ok(row),
+            _ => Ok(default_header()),
+        }
+    }
+
+    // Reads the card backwards from its end until the start of its last row. Returns
+    // `None` if the last row can't be told apart from the rows above it, e.g. because the
+    // card ends within a quoted field.
+    fn last_row(&self) -> Result<Option<StringRecord>> {
+        let mut file = File::open(&self.0).chain_err(|| "Failed to read card file")?;
+        let size = file.metadata()?.len();
+
+        let mut chunk_size = TAIL_CHUNK_SIZE;
+        loop {
+            let offset = size.saturating_sub(chunk_size);
+            let mut tail = Vec::new();
+            file.seek(SeekFrom::Start(offset))?;
+            (&mut file).take(size - offset).read_to_end(&mut tail)?;
+
+            let start = match start_of_last_row(&tail) {
+                Some(start) => start,
+                None if offset == 0 => return Ok(None),
+                None => {
+                    chunk_size *= 2;
+                    continue;
+                }
+            };
+
+            let last_row = String::from_utf8(tail.split_off(start))
+                .chain_err(|| "Failed to read card file")?;
+            if ends_in_quoted_field(&last_row) {
+                return Ok(None);
+            }
+
+            let mut rows = reader(&last_row).into_records();
+            return Ok(match (rows.next(), rows.next()) {
+                (Some(Ok(row)), None) => Some(row),
+                _ => None,
+            });
+        }
+    }
+}
+
+impl CardStore for CsvStore {
+    fn query(&self, since: Option<Timestamp>, until: Option<Timestamp>) -> Result<Vec<Record>> {
+        let records = deserialize(&self.snapshot()?)?;
+
+        Ok(records
+            .into_iter()
+            .filter(|r| r.overlaps(since, until))
+            .collect())
+    }
+
+    fn last(&self) -> Result<Option<Record>> {
+        let header = self.header()?;
+        let record = match self.last_row()? {
+            Some(row) if !is_header(&row) => row.deserialize(Some(&header)).ok(),
+            _ => None,
+        };
+
+        // Cards without records, comments and broken rows at the end of the card are left
+        // to reading all rows, which skips the comments and names the line of broken rows
+        match record {
+            Some(record) => Ok(Some(record)),
+            None => Ok(self.load()?.into_iter().next()),
+        }
+    }
+
+    fn write(&self, records: &[Record]) -> Result<()> {
+        // Appended rows must match the columns of the card, which may have been
+        // reordered by hand
+        if self.header()? != default_header() {
+            let mut all = self.load()?;
+            all.retain(|r| records.iter().all(|new| new.i != r.i));
+            all.extend(records.iter().cloned());
+            // Indices are chronological
+            all.sort_by_key(|r| std::cmp::Reverse(r.i));
+
+            return self.store(&all);
+        }
+
+        // Rows appended after a quoted field that was cut off would end up in that field,
+        // reading all rows fails on it in that case
+        if self.last_row()?.is_none() {
+            self.load()?;
+        }
+
+        let (size_before, size_after) = self.append(records)?;
+
+        if size_before / COMPACTION_INTERVAL != size_after / COMPACTION_INTERVAL {
+            // Cards that can't be parsed are left to `punch doctor`
+            if let Ok(compacted) = self.load() {
+                self.store(&compacted)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store(&self, records: &[Record]) -> Result<()> {
+        self.restore(&serialize(records)?)
+    }
+
+    fn snapshot(&self) -> Result<String> {
+        fs::read_to_string(&self.0).chain_err(|| "Failed to read card file")
+    }
+
+    fn restore(&self, snapshot: &str) -> Result<()> {
+        file::write_atomically(&self.0, snapshot).chain_err(|| "Could not write to card file")
+    }
+
+    fn is_outdated(&self) -> Result<bool> {
+        // The metadata line of current cards suffices, older cards are read completely
+        let mut first_line = String::new();
+        let file = File::open(&self.0).chain_err(|| "Failed to read card file")?;
+        BufReader::new(file)
+            .read_line(&mut first_line)
+            .chain_err(|| "Failed to read card file")?;
+        if first_line.starts_with(METADATA_PREFIX) {
+            return Ok(version(&first_line)? < VERSION);
+        }
+
+        Ok(version(&self.snapshot()?)? < VERSION)
+    }
+
+    fn migrate(&self) -> Result<()> {
+        let contents = self.snapshot()?;
+        if version(&contents)? == VERSION {
+            return Ok(());
+        }
+
+        self.restore(&migrate_first_layout(&contents))
+    }
+}
+
+/// Returns a reader that skips the metadata line but not the header row
+pub fn reader(contents: &str) -> Reader<&[u8]> {
+    reader_builder().from_reader(contents.as_bytes())
+}
+
+fn reader_builder() -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'));
+
+    builder
+}
+
+// Returns the position of the line break before the last row. Line breaks within quoted
+// fields don't end a row. As long as the last row is complete, scanning backwards from
+// the end of the card tells them apart just like scanning from its start would.
+fn start_of_last_row(tail: &[u8]) -> Option<usize> {
+    let end = tail
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |n| n + 1);
+
+    let mut is_quoted = false;
+    for (n, b) in tail[..end].iter().enumerate().rev() {
+        match b {
+            b'"' => is_quoted = !is_quoted,
+            b'\n' if !is_quoted => return Some(n + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Whether the card ends within a quoted field, which the CSV reader reads up to the end
+/// of the card. Appending a row that holds a quoted field may leave such a field behind
+/// when interrupted.
+pub fn ends_in_quoted_field(contents: &str) -> bool {
+    let mut is_quoted = false;
+    let mut is_field_start = true;
+    let mut is_row_start = true;
+
+    let mut bytes = contents.bytes().peekable();
+    while let Some(b) = bytes.next() {
+        match b {
+            // Quotes within quoted fields are escaped by doubling them
+            b'"' if is_quoted && bytes.peek() == Some(&b'"') => {
+                bytes.next();
+            }
+            b'"' if is_quoted => is_quoted = false,
+            // Quotes within unquoted fields are read as they are
+            b'"' if is_field_start => is_quoted = true,
+            b'#' if is_row_start => {
+                bytes.find(|&b| b == b'\n');
+                continue;
+            }
+            b',' | b'\n' if !is_quoted => {
+                is_field_start = true;
+                is_row_start = b == b'\n';
+                continue;
+            }
+            _ => {}
+        }
+        is_field_start = false;
+        is_row_start = false;
+    }
+
+    is_quoted
+}
+
+/// Whether a row names the columns instead of holding a record
+pub fn is_header(row: &StringRecord) -> bool {
+    row.get(0) == Some(Record::FIELDS[0])
+}
+
+/// The columns of cards that lack a header row
+pub fn default_header() -> StringRecord {
+    StringRecord::from(Record::FIELDS.to_vec())
+}
+
+fn preamble() -> String {
+    format!(
+        "{}{}\n{}\n",
+        METADATA_PREFIX,
+        VERSION,
+        Record::FIELDS.join(",")
+    )
+}
+
+fn version(contents: &str) -> Result<usize> {
+    let first_line = contents.lines().next().unwrap_or_default();
+    if let Some(version) = first_line.strip_prefix(METADATA_PREFIX) {
+        return match version.trim().parse() {
+            Ok(VERSION) => Ok(VERSION),
+            _ => Err(ErrorKind::UnsupportedCardVersion(version.trim().into()).into()),
+        };
+    }
+
+    // Cards of the first versions lack both the metadata line and the header row, the
+    // metadata line alone may have been removed by hand
+    let first_row = reader(contents).records().next().and_then(|row| row.ok());
+
+    Ok(match first_row {
+        Some(row) if is_header(&row) => VERSION,
+        _ => 0,
+    })
+}
+
+// Cards of the first versions are ordered newest first and lack the metadata line, the
+// header row and the project column. Rows are moved around as they are, so that rows that
+// can't be parsed are kept for `punch doctor`, and are read without a project.
+fn migrate_first_layout(contents: &str) -> String {
+    let mut rows = raw_rows(contents);
+    rows.reverse();
+
+    let rows = rows
+        .into_iter()
+        .map(|row| format!("{}\n", row.trim_end_matches(['\r', '\n'])))
+        .collect::<String>();
+
+    preamble() + &rows
+}
+
+// Splits the card into the text of its rows, including quoted line breaks
+fn raw_rows(contents: &str) -> Vec<&str> {
+    let mut reader = reader(contents);
+    let mut row = StringRecord::new();
+    let mut starts = Vec::new();
+
+    while let Ok(true) = reader.read_record(&mut row) {
+        starts.push(row.position().map_or(0, |p| p.byte() as usize));
+    }
+
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain(Some(&contents.len())))
+        .map(|(&start, &end)| &contents[start..end])
+        .collect()
+}
+
+/// Serializes records that are ordered newest first into a card ordered oldest first
+pub fn serialize(records: &[Record]) -> Result<String> {
+    Ok(preamble() + &serialize_rows(records)?)
+}
+
+fn serialize_rows(records: &[Record]) -> Result<String> {
+    let mut writer = WriterBuilder::new()
+        .flexible(true)
+        .has_headers(false)
+        .from_writer(vec![]);
+
+    for r in records.iter().rev() {
+        writer.serialize(r)?;
+    }
+
+    let contents = writer
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .chain_err(|| "Could not serialize records")?;
+
+    String::from_utf8(contents).chain_err(|| "Could not serialize records")
+}
+
+/// Parses all records, newest first, and fails on the first one that can't be parsed
+pub fn deserialize(contents: &str) -> Result<Vec<Record>> {
+    let mut records = doctor::supersede(read(contents)?.1, |r| r);
+    records.reverse();
+
+    Ok(records)
+}
+
+// Returns the header and all rows in their order in the card
+fn read(contents: &str) -> Result<(StringRecord, Vec<Record>)> {
+    let mut header = default_header();
+    let mut records = Vec::new();
+    let mut last_row = None;
+
+    for (n, row) in reader(contents).records().enumerate() {
+        let row = row.map_err(|e| malformed_record(e, &header, None))?;
+        last_row = Some((row.position().map_or(0, |p| p.line()), row.len()));
+
+        if n == 0 && is_header(&row) {
+            header = row;
+            continue;
+        }
+
+        let record = row
+            .deserialize(Some(&header))
+            .map_err(|e| malformed_record(e, &header, Some(&row)))?;
+        records.push(record);
+    }
+
+    if let Some((line, num_fields)) = last_row.filter(|_| ends_in_quoted_field(contents)) {
+        let field = header.get(num_fields.saturating_sub(1)).unwrap_or("?");
+
+        return Err(ErrorKind::MalformedRecord(line, field.into(), UNTERMINATED.into()).into());
+    }
+
+    Ok((header, records))
+}
+
+fn malformed_record(
+    error: ::csv::Error,
+    header: &StringRecord,
+    row: Option<&StringRecord>,
+) -> Error {
+    let line = error.position().map_or(0, |p| p.line());
+
+    match error.kind() {
+        ::csv::ErrorKind::Deserialize { err, .. } => {
+            // Timestamps fail with custom errors that don't carry the field,
+            // so the field is determined by parsing each timestamp on its own
+            let field = err.field().map(|n| n as usize).or_else(|| {
+                row?.iter().enumerate().find_map(|(n, value)| {
+                    let is_timestamp = matches!(header.get(n), Some("start") | Some("end"));
+                    let single = StringRecord::from(vec![value]);
+                    let is_invalid = is_timestamp
+                        && !value.is_empty()
+                        && single.deserialize::<Timestamp>(None).is_err();
+
+                    Some(n).filter(|_| is_invalid)
+                })
+            });
+            let field = field
+                .and_then(|n| header.get(n))
+                .map_or(String::from("?"), String::from);
+
+            ErrorKind::MalformedRecord(line, field, err.kind().to_string()).into()
+        }
+        ::csv::ErrorKind::Io(_) => error.into(),
+        _ => ErrorKind::MalformedRecord(line, String::from("?"), error.to_string()).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{card, legacy_row, record, row, TempDir};
+
+    // Opens a store holding the given contents in the directory
+    fn open(dir: &TempDir, contents: &str) -> CsvStore {
+        let path = dir.path().join("main.csv");
+        fs::write(&path, contents).unwrap();
+
+        CsvStore::open(path).unwrap()
+    }
+
+    fn notes(contents: &str) -> Vec<(usize, String)> {
+        deserialize(contents)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.i, r.note.unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn versions_of_cards() {
+        assert_eq!(version(&preamble()).unwrap(), VERSION);
+        assert!(version("# punch card v2\ni,start,end,note\n").is_err());
+        assert!(version("# punch card v9\n").is_err());
+
+        // Cards without metadata line
+        assert_eq!(version("i,start,end,note,project\n").unwrap(), VERSION);
+        let card = legacy_row(1, 10, Some(11), "b") + &legacy_row(0, 8, Some(9), "a");
+        assert_eq!(version(&card).unwrap(), 0);
+        assert_eq!(version("").unwrap(), 0);
+    }
+
+    #[test]
+    fn migrating_cards_of_the_first_layout() {
+        let card = legacy_row(1, 10, Some(11), "\"b\nb\"")
+            + &legacy_row(0, 8, Some(9), "a").replace('\n', "\r\n");
+        let migrated = migrate_first_layout(&card);
+
+        assert!(migrated.starts_with(&preamble()));
+        assert_eq!(version(&migrated).unwrap(), VERSION);
+        assert_eq!(notes(&migrated), [(1, "b\nb".into()), (0, "a".into())]);
+        assert!(deserialize(&migrated)
+            .unwrap()
+            .iter()
+            .all(|r| r.project.is_none()));
+    }
+
+    #[test]
+    fn migrating_keeps_unparseable_rows() {
+        let card =
+            legacy_row(2, 12, Some(13), "c") + "1,noon,,b\n" + &legacy_row(0, 8, Some(9), "a");
+        let migrated = migrate_first_layout(&card);
+
+        assert_eq!(
+            migrated,
+            preamble()
+                + &legacy_row(0, 8, Some(9), "a")
+                + "1,noon,,b\n"
+                + &legacy_row(2, 12, Some(13), "c")
+        );
+        assert!(deserialize(&migrated).is_err());
+    }
+
+    #[test]
+    fn quoted_fields_at_the_end_of_cards() {
+        assert!(!ends_in_quoted_field(&card(&[row(
+            0,
+            8,
+            Some(9),
+            "\"say \"\"hi\"\"\""
+        )])));
+        assert!(!ends_in_quoted_field(&card(&[row(
+            0,
+            8,
+            Some(9),
+            "\"two\nlines\""
+        )])));
+        assert!(!ends_in_quoted_field(&card(&[row(
+            0,
+            8,
+            Some(9),
+            "say \"hi"
+        )])));
+        assert!(!ends_in_quoted_field(&(card(&[]) + "# \"comment\n")));
+
+        assert!(ends_in_quoted_field(&card(&[row(
+            0,
+            8,
+            Some(9),
+            "\"cut off"
+        )])));
+        assert!(ends_in_quoted_field(&card(&[row(
+            0,
+            8,
+            Some(9),
+            "\"say \"\"hi"
+        )])));
+    }
+
+    #[test]
+    fn last_records() {
+        let dir = TempDir::new();
+        assert_eq!(open(&dir, &card(&[])).last().unwrap(), None);
+
+        let rows = [row(0, 8, Some(9), "\"a\na\""), row(1, 10, None, "b")];
+        assert_eq!(
+            open(&dir, &card(&rows)).last().unwrap(),
+            Some(record(1, 10, None, "b"))
+        );
+
+        let rows = [row(0, 8, Some(9), "a"), row(1, 10, Some(11), "\"b\nb\"")];
+        assert_eq!(
+            open(&dir, &card(&rows)).last().unwrap(),
+            Some(record(1, 10, Some(11), "b\nb"))
+        );
+    }
+
+    #[test]
+    fn last_records_of_torn_cards() {
+        let dir = TempDir::new();
+
+        // Punching out was interrupted while appending the row
+        let rows = [
+            row(0, 8, Some(9), "a"),
+            row(1, 10, None, "b"),
+            row(1, 10, Some(11), "\"b cut off"),
+        ];
+        assert!(matches!(
+            open(&dir, &card(&rows)).last(),
+            Err(Error(ErrorKind::MalformedRecord(5, field, _), _)) if field == "note"
+        ));
+
+        // Quoted line breaks above the torn row pass for the start of the last row
+        let rows = [
+            row(0, 8, Some(9), "\"a\na\""),
+            row(1, 10, None, "\"b cut off"),
+        ];
+        assert!(matches!(
+            open(&dir, &card(&rows)).last(),
+            Err(Error(ErrorKind::MalformedRecord(5, ..), _))
+        ));
+    }
+
+    #[test]
+    fn superseding_records() {
+        let dir = TempDir::new();
+        let store = open(&dir, &card(&[row(0, 8, Some(9), "a")]));
+
+        store.write(&[record(1, 10, None, "b")]).unwrap();
+        store.write(&[record(1, 10, Some(11), "b")]).unwrap();
+
+        assert_eq!(
+            store.snapshot().unwrap(),
+            card(&[
+                row(0, 8, Some(9), "a"),
+                row(1, 10, None, "b"),
+                row(1, 10, Some(11), "b")
+            ])
+        );
+        assert_eq!(store.last().unwrap(), Some(record(1, 10, Some(11), "b")));
+        assert_eq!(store.load().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn appending_to_torn_cards() {
+        let dir = TempDir::new();
+        let contents = card(&[row(0, 8, Some(9), "a"), row(1, 10, None, "\"b cut off")]);
+        let store = open(&dir, &contents);
+
+        // The punch out would end up in the note of the torn row
+        assert!(store.write(&[record(1, 10, Some(11), "b")]).is_err());
+        assert_eq!(store.snapshot().unwrap(), contents);
+    }
+}
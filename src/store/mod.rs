@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+
+use crate::err::*;
+use crate::record::Record;
+use crate::time::Timestamp;
+
+pub mod csv;
+mod sqlite;
+
+pub use self::csv::CsvStore;
+pub use self::sqlite::SqliteStore;
+
+/// Persistence of the records of a single card. Records are always returned newest first.
+pub trait CardStore {
+    /// Returns all records that overlap with the given range. Missing bounds are unbounded.
+    fn query(&self, since: Option<Timestamp>, until: Option<Timestamp>) -> Result<Vec<Record>>;
+
+    fn load(&self) -> Result<Vec<Record>> {
+        self.query(None, None)
+    }
+
+    /// Returns the newest record
+    fn last(&self) -> Result<Option<Record>> {
+        Ok(self.load()?.into_iter().next())
+    }
+
+    /// Adds new records and replaces existing ones with the same index
+    fn write(&self, records: &[Record]) -> Result<()>;
+
+    /// Replaces all records
+    fn store(&self, records: &[Record]) -> Result<()>;
+
+    /// Returns the card in CSV format, which is used for journaling and editing
+    fn snapshot(&self) -> Result<String>;
+
+    /// Replaces the card with a snapshot
+    fn restore(&self, snapshot: &str) -> Result<()>;
+
+    /// Whether the card was written by an older version of punch
+    fn is_outdated(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Converts the card to the current format
+    fn migrate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+arg_enum! {
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Backend {
+        Csv,
+        Sqlite,
+    }
+}
+
+// arg_enum! does not accept #[default] on variants
+#[allow(clippy::derivable_impls)]
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Csv
+    }
+}
+
+impl Backend {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Backend::Csv => "csv",
+            Backend::Sqlite => "sqlite",
+        }
+    }
+
+    /// Determines the backend of a card file by its extension
+    pub fn of(path: &Path) -> Option<Self> {
+        let ext = path.extension()?;
+
+        Backend::all().find(|backend| ext == backend.extension())
+    }
+
+    pub fn all() -> impl Iterator<Item = Self> {
+        [Backend::Csv, Backend::Sqlite].iter().copied()
+    }
+
+    pub fn open(self, path: PathBuf) -> Result<Box<dyn CardStore>> {
+        match self {
+            Backend::Csv => Ok(Box::new(CsvStore::open(path)?)),
+            Backend::Sqlite => Ok(Box::new(SqliteStore::open(&path)?)),
+        }
+    }
+}
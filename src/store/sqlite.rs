@@ -0,0 +1,149 @@
+use std::convert::TryFrom;
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use super::csv;
+use super::CardStore;
+use crate::err::*;
+use crate::record::Record;
+use crate::time::Timestamp;
+
+// Timestamps are stored as RFC 3339 to keep their offset, and as milliseconds since the
+// epoch to query them by range
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS records (
+        i INTEGER PRIMARY KEY,
+        start TEXT NOT NULL,
+        start_ms INTEGER NOT NULL,
+        "end" TEXT,
+        end_ms INTEGER,
+        note TEXT,
+        project TEXT
+    );
+    CREATE INDEX IF NOT EXISTS records_by_start ON records (start_ms);
+    CREATE INDEX IF NOT EXISTS records_by_end ON records (end_ms);
+"#;
+
+/// Stores records in an SQLite database, which can be queried by date range
+pub struct SqliteStore(Connection);
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path).chain_err(|| "Failed to open card database")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(SqliteStore(connection))
+    }
+
+    fn insert(&self, record: &Record) -> Result<()> {
+        self.0.execute(
+            r#"INSERT OR REPLACE INTO records (i, start, start_ms, "end", end_ms, note, project)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
+            params![
+                record.i as i64,
+                record.start.to_rfc3339(),
+                record.start.timestamp_millis(),
+                record.end.map(|end| end.to_rfc3339()),
+                record.end.map(|end| end.timestamp_millis()),
+                record.note,
+                record.project,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    // Returns the newest records first, all of them if there is no limit
+    fn select(
+        &self,
+        since: Option<Timestamp>,
+        until: Option<Timestamp>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Record>> {
+        let mut statement = self.0.prepare(
+            r#"SELECT i, start, "end", note, project FROM records
+               WHERE (?1 IS NULL OR end_ms IS NULL OR end_ms > ?1)
+                 AND (?2 IS NULL OR start_ms < ?2)
+               ORDER BY start_ms DESC, i DESC
+               LIMIT ?3"#,
+        )?;
+
+        let rows = statement
+            .query_map(
+                params![
+                    since.map(|t| t.timestamp_millis()),
+                    until.map(|t| t.timestamp_millis()),
+                    // A negative limit means no limit
+                    limit.map_or(-1, |limit| limit as i64),
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                    ))
+                },
+            )?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(i, start, end, note, project)| {
+                Ok(Record {
+                    i: i as usize,
+                    start: Timestamp::try_from(start.as_str())?,
+                    end: end
+                        .map(|end| Timestamp::try_from(end.as_str()))
+                        .transpose()?,
+                    note,
+                    project,
+                })
+            })
+            .collect()
+    }
+}
+
+impl CardStore for SqliteStore {
+    fn query(&self, since: Option<Timestamp>, until: Option<Timestamp>) -> Result<Vec<Record>> {
+        self.select(since, until, None)
+    }
+
+    fn last(&self) -> Result<Option<Record>> {
+        Ok(self.select(None, None, Some(1))?.into_iter().next())
+    }
+
+    fn write(&self, records: &[Record]) -> Result<()> {
+        let transaction = self.0.unchecked_transaction()?;
+
+        for r in records {
+            self.insert(r)?;
+        }
+
+        transaction
+            .commit()
+            .chain_err(|| "Could not write to card database")
+    }
+
+    fn store(&self, records: &[Record]) -> Result<()> {
+        let transaction = self.0.unchecked_transaction()?;
+
+        transaction.execute("DELETE FROM records", [])?;
+        for r in records {
+            self.insert(r)?;
+        }
+
+        transaction
+            .commit()
+            .chain_err(|| "Could not write to card database")
+    }
+
+    fn snapshot(&self) -> Result<String> {
+        csv::serialize(&self.load()?)
+    }
+
+    fn restore(&self, snapshot: &str) -> Result<()> {
+        self.store(&csv::deserialize(snapshot)?)
+    }
+}
@@ -0,0 +1,90 @@
+// Fixtures shared by the unit tests. Records lie on the same day, from and to the
+// given hours in UTC.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::record::Record;
+use crate::time::Timestamp;
+
+pub fn at(hour: u32) -> Timestamp {
+    Timestamp::try_from(format!("2026-10-01T{:02}:00:00+00:00", hour).as_str()).unwrap()
+}
+
+pub fn record(i: usize, start: u32, end: Option<u32>, note: &str) -> Record {
+    Record {
+        i,
+        start: at(start),
+        end: end.map(at),
+        note: Some(note.to_string()).filter(|note| !note.is_empty()),
+        project: None,
+    }
+}
+
+/// Returns the row of a record in the current layout of CSV cards
+pub fn row(i: usize, start: u32, end: Option<u32>, note: &str) -> String {
+    format!("{},\n", fields(i, start, end, note))
+}
+
+/// Returns the row of a record in cards from before the project column
+pub fn legacy_row(i: usize, start: u32, end: Option<u32>, note: &str) -> String {
+    format!("{}\n", fields(i, start, end, note))
+}
+
+// Notes are inserted as they are, so that they can hold broken CSV
+fn fields(i: usize, start: u32, end: Option<u32>, note: &str) -> String {
+    let end = end.map_or(String::new(), |end| at(end).to_rfc3339());
+
+    format!("{},{},{},{}", i, at(start).to_rfc3339(), end, note)
+}
+
+/// Returns a card in the current layout with the given rows
+pub fn card(rows: &[String]) -> String {
+    format!(
+        "# punch card v3\ni,start,end,note,project\n{}",
+        rows.concat()
+    )
+}
+
+/// Returns the index, the hour of the end and the note of each record
+pub fn summary(records: &[Record]) -> Vec<(usize, Option<String>, String)> {
+    records
+        .iter()
+        .map(|r| {
+            let end = r.end.map(|end| end.format("%H"));
+            (r.i, end, r.note.clone().unwrap_or_default())
+        })
+        .collect()
+}
+
+/// A directory of its own for each test, removed along with its contents when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!(
+            "punch-test-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
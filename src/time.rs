@@ -1,13 +1,13 @@
-use chrono::offset::Local;
+use chrono::offset::{FixedOffset, Local, TimeZone};
 use chrono::{self};
 use colored::*;
-use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer};
 use std::cmp::Ordering;
-use std::convert::Into;
 use std::convert::TryFrom;
+use std::fmt;
 use std::iter::Sum;
-use std::ops::Add;
+use std::ops::{Add, Sub};
 
 use crate::err::*;
 use crate::format::RecordFormattingOptions;
@@ -22,9 +22,9 @@ impl From<chrono::Duration> for Duration {
         Self(d)
     }
 }
-impl Into<chrono::Duration> for Duration {
-    fn into(self) -> chrono::Duration {
-        self.0
+impl From<Duration> for chrono::Duration {
+    fn from(d: Duration) -> Self {
+        d.0
     }
 }
 
@@ -58,21 +58,51 @@ impl Sum for Duration {
     }
 }
 
+impl TryFrom<&str> for Duration {
+    type Error = Error;
+
+    /// Parses durations like "15min", "2h" or "1h30m" by summing up all amount-unit pairs
+    fn try_from(string: &str) -> Result<Self> {
+        let mut chunks = Vec::new();
+        let mut chunk = String::new();
+
+        for c in string.trim().chars() {
+            if c.is_ascii_digit() && chunk.ends_with(|prev: char| prev.is_alphabetic()) {
+                chunks.push(chunk);
+                chunk = String::new();
+            }
+            chunk.push(c);
+        }
+        chunks.push(chunk);
+
+        let seconds = chunks
+            .iter()
+            .map(|chunk| RoundingGranularityInSeconds::try_from(chunk.as_str()).map(|g| g.0))
+            .sum::<Result<usize>>()
+            .chain_err(|| ErrorKind::InvalidDuration(string.into()))?;
+
+        Ok(Self::from_seconds(seconds))
+    }
+}
+
 impl Duration {
     fn from_seconds(seconds: usize) -> Self {
         Self(chrono::Duration::seconds(seconds as i64))
     }
     pub fn of_record(r: &Record) -> Self {
-        let end = r.end.clone().unwrap_or_else(Timestamp::now);
+        let end = r.end.unwrap_or_else(Timestamp::now);
 
         Self::between(r.start, end)
     }
     fn between(from: Timestamp, to: Timestamp) -> Self {
         Self(to.0.signed_duration_since(from.0))
     }
-    fn in_seconds(&self) -> usize {
+    pub fn in_seconds(&self) -> usize {
         self.0.num_seconds() as usize
     }
+    pub fn in_hours(&self) -> f64 {
+        self.0.num_seconds() as f64 / 3600_f64
+    }
     pub fn one_minute() -> Self {
         Self(chrono::Duration::minutes(1))
     }
@@ -82,7 +112,7 @@ impl Duration {
     pub fn round(&self, opt: &RoundingOptions) -> Self {
         let exact_seconds = self.in_seconds();
 
-        if exact_seconds % opt.granularity.0 == 0 {
+        if exact_seconds.is_multiple_of(opt.granularity.0) {
             return Self::from_seconds(exact_seconds);
         }
 
@@ -120,8 +150,9 @@ impl Duration {
     }
 }
 
+/// A point in time along with the UTC offset it was recorded with
 #[derive(Clone, Copy, Debug)]
-pub struct Timestamp(chrono::DateTime<Local>);
+pub struct Timestamp(chrono::DateTime<FixedOffset>);
 
 impl PartialEq for Timestamp {
     fn eq(&self, other: &Self) -> bool {
@@ -143,6 +174,26 @@ impl Add<Duration> for Timestamp {
     }
 }
 
+impl Sub<Duration> for Timestamp {
+    type Output = Self;
+
+    fn sub(self, other: Duration) -> Self {
+        Self(self.0 - Into::<chrono::Duration>::into(other))
+    }
+}
+
+impl TryFrom<&str> for Timestamp {
+    type Error = Error;
+
+    /// Parses RFC 3339 timestamps as well as local times like "09:15", "yesterday 17:30",
+    /// "monday 09:15" or "2020-02-14 09:15". Days without a time, like "yesterday",
+    /// "monday" or "2020-02-14", refer to the start of the day. Durations like "-2w"
+    /// refer to that long ago.
+    fn try_from(string: &str) -> Result<Self> {
+        Self::parse_in(string, Zone::Local)
+    }
+}
+
 impl Serialize for Timestamp {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -164,6 +215,45 @@ impl<'de> Deserialize<'de> for Timestamp {
 }
 
 impl Timestamp {
+    /// Parses a timestamp like `Timestamp::try_from`, but reads days and times without
+    /// offset in the zone, e.g. "today" as the start of today in the home timezone
+    pub fn parse_in(string: &str, zone: Zone) -> Result<Self> {
+        let string = string.trim();
+        let invalid = || Error::from(ErrorKind::InvalidTimestamp(string.into()));
+
+        if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(string) {
+            return Ok(Self(dt));
+        }
+
+        if let Some(ago) = string.strip_prefix('-') {
+            let ago = Duration::try_from(ago).map_err(|_| invalid())?;
+            return Ok(Self::now() - ago);
+        }
+
+        let now = Self::now().in_zone(zone);
+        let today = now.date_naive();
+        if let Some(day) = Self::parse_day(string, today) {
+            return Self::start_of_day(day, zone, &now).ok_or_else(invalid);
+        }
+
+        let (day, time) = match string.rsplit_once(' ') {
+            Some((day, time)) => (Some(day.trim()), time),
+            None => (None, string),
+        };
+
+        let date = match day {
+            None => today,
+            Some(day) => Self::parse_day(day, today).ok_or_else(invalid)?,
+        };
+        let time = chrono::NaiveTime::parse_from_str(time, "%H:%M")
+            .or_else(|_| chrono::NaiveTime::parse_from_str(time, "%H:%M:%S"))
+            .map_err(|_| invalid())?;
+
+        // Local times that are skipped or repeated by DST transitions are rejected
+        Self::resolve(date.and_time(time), zone, &now)
+            .single()
+            .ok_or_else(invalid)
+    }
     pub fn format(&self, format_str: &str) -> String {
         self.0.format(format_str).to_string()
     }
@@ -180,21 +270,234 @@ impl Timestamp {
             self.0.format("%F %T").to_string()
         }
     }
+    pub fn to_rfc3339(self) -> String {
+        // Same format as the serde implementation
+        self.0.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+    }
+    /// Formats the timestamp in UTC as iCalendar expects it
+    pub fn to_ics(self) -> String {
+        self.0
+            .with_timezone(&chrono::Utc)
+            .format("%Y%m%dT%H%M%SZ")
+            .to_string()
+    }
+    pub fn date_naive(self) -> chrono::NaiveDate {
+        self.0.date_naive()
+    }
+    pub fn timestamp_millis(self) -> i64 {
+        self.0.timestamp_millis()
+    }
     pub fn now() -> Self {
-        Timestamp(Local::now())
+        Timestamp(Local::now().fixed_offset())
+    }
+    /// Interprets a date and time without offset in UTC
+    pub fn from_utc(dt: chrono::NaiveDateTime) -> Self {
+        Timestamp(chrono::Utc.from_utc_datetime(&dt).fixed_offset()).in_zone(Zone::Local)
+    }
+    /// Interprets a date and time without offset in the local timezone. Times that are
+    /// repeated by DST transitions refer to their first occurrence.
+    pub fn from_local(dt: chrono::NaiveDateTime) -> Option<Self> {
+        Self::resolve(dt, Zone::Local, &Self::now()).earliest()
+    }
+    pub fn from_unix(seconds: i64) -> Option<Self> {
+        chrono::DateTime::from_timestamp(seconds, 0)
+            .map(|dt| Timestamp(dt.fixed_offset()).in_zone(Zone::Local))
+    }
+    /// Converts the timestamp to the offset of the zone at that time. The recorded
+    /// offset is kept for `Zone::Recorded`.
+    pub fn in_zone(self, zone: Zone) -> Self {
+        match zone {
+            Zone::Local => Timestamp(self.0.with_timezone(&Local).fixed_offset()),
+            Zone::Named(tz) => Timestamp(self.0.with_timezone(&tz).fixed_offset()),
+            Zone::Recorded => self,
+        }
+    }
+    // Interprets a date and time in the zone, or in the offset of the reference for
+    // `Zone::Recorded`. Times that are skipped by DST transitions don't exist, repeated
+    // ones are ambiguous.
+    fn resolve(
+        dt: chrono::NaiveDateTime,
+        zone: Zone,
+        reference: &Self,
+    ) -> chrono::LocalResult<Self> {
+        match zone {
+            Zone::Local => Local.from_local_datetime(&dt).map(|dt| dt.fixed_offset()),
+            Zone::Named(tz) => tz.from_local_datetime(&dt).map(|dt| dt.fixed_offset()),
+            Zone::Recorded => reference.0.offset().from_local_datetime(&dt),
+        }
+        .map(Timestamp)
+    }
+    // Seconds since the epoch as if the local time of the timestamp was UTC, so that
+    // dividing them gives local minutes and hours
+    fn local_seconds(&self) -> i64 {
+        self.0.timestamp() + i64::from(self.0.offset().local_minus_utc())
+    }
+    /// Returns the start of a month like "2020-02" and the start of the month after
+    pub fn bounds_of_month(month: &str) -> Result<(Self, Self)> {
+        let invalid = || Error::from(ErrorKind::InvalidMonth(month.into()));
+
+        let first_day = chrono::NaiveDate::parse_from_str(&format!("{}-01", month.trim()), "%F")
+            .map_err(|_| invalid())?;
+        let next_first_day = first_day
+            .checked_add_months(chrono::Months::new(1))
+            .ok_or_else(invalid)?;
+
+        let now = Self::now();
+
+        Ok((
+            Self::start_of_day(first_day, Zone::Local, &now).ok_or_else(invalid)?,
+            Self::start_of_day(next_first_day, Zone::Local, &now).ok_or_else(invalid)?,
+        ))
+    }
+    fn start_of_day(date: chrono::NaiveDate, zone: Zone, reference: &Self) -> Option<Self> {
+        let midnight = date.and_hms_opt(0, 0, 0)?;
+
+        // Midnight may be skipped by DST transitions in some timezones, the day then
+        // starts an hour later
+        Self::resolve(midnight, zone, reference)
+            .earliest()
+            .or_else(|| {
+                Self::resolve(midnight + chrono::Duration::hours(1), zone, reference).earliest()
+            })
     }
-    pub fn floor_to_interval_units(&self, interval: Interval) -> u32 {
+    // Parses "today", "yesterday", the name of a weekday within the last week, or a date
+    fn parse_day(string: &str, today: chrono::NaiveDate) -> Option<chrono::NaiveDate> {
         use chrono::Datelike;
 
-        let year = self.0.year() as u32;
+        match string.to_lowercase().as_str() {
+            "today" => Some(today),
+            "yesterday" => today.pred_opt(),
+            day => match day.parse::<chrono::Weekday>() {
+                Ok(weekday) => {
+                    let days_ago = (7 + today.weekday().num_days_from_monday()
+                        - weekday.num_days_from_monday())
+                        % 7;
+                    today.checked_sub_days(chrono::Days::new(days_ago.into()))
+                }
+                Err(_) => chrono::NaiveDate::parse_from_str(day, "%F").ok(),
+            },
+        }
+    }
+    /// Returns the start of the interval unit that is `offset` units after the one that
+    /// contains the timestamp, e.g. the start of last month for `Interval::Month` and -1.
+    /// Units are those of the zone of the calendar.
+    pub fn start_of_interval(&self, interval: Interval, offset: i32, calendar: &Calendar) -> Self {
+        use chrono::Datelike;
+
+        let this = self.in_zone(calendar.zone);
+
+        let unit_in_seconds = match interval {
+            Interval::Second => 1,
+            Interval::Minute => 60,
+            Interval::Hour => 3600,
+            _ => 0,
+        };
+        if unit_in_seconds > 0 {
+            let units = this.local_seconds().div_euclid(unit_in_seconds) + i64::from(offset);
+            let utc_offset = this.0.offset().local_minus_utc();
+            let dt = chrono::DateTime::from_timestamp(
+                units * unit_in_seconds - i64::from(utc_offset),
+                0,
+            )
+            .expect("Timestamp out of range");
+            return Timestamp(dt.with_timezone(this.0.offset())).in_zone(calendar.zone);
+        }
+
+        let date = this.0.date_naive();
+        let shift_days = |date: chrono::NaiveDate, days: i64| date + chrono::Duration::days(days);
+        let shift_months = |date: chrono::NaiveDate, months: i32| {
+            let months = chrono::Months::new(months.unsigned_abs());
+            match offset >= 0 {
+                true => date.checked_add_months(months),
+                false => date.checked_sub_months(months),
+            }
+            .expect("Date out of range")
+        };
+
+        let first_day = match interval {
+            Interval::Day => shift_days(date, offset.into()),
+            Interval::Week => shift_days(calendar.start_of_week(date), 7 * i64::from(offset)),
+            Interval::Month => shift_months(date.with_day(1).unwrap(), offset),
+            Interval::Quarter => shift_months(calendar.start_of_quarter(date), 3 * offset),
+            Interval::Year => shift_months(date.with_ordinal(1).unwrap(), 12 * offset),
+            Interval::FiscalYear => {
+                let first_day = calendar.start_of_fiscal_year(calendar.fiscal_year_of(date));
+                shift_months(first_day, 12 * offset)
+            }
+            _ => unreachable!(),
+        };
+
+        Self::start_of_day(first_day, calendar.zone, &this)
+            .expect("Days start within an hour after midnight")
+    }
+    /// Returns the start of the span that is `offset` spans after the one that contains
+    /// the timestamp. Spans of several units are counted from the period anchor of the
+    /// calendar, e.g. every other week since the start of a sprint.
+    pub fn start_of_span(&self, span: Span, offset: i32, calendar: &Calendar) -> Self {
+        if span.length == 1 {
+            return self.start_of_interval(span.interval, offset, calendar);
+        }
+
+        let anchor = Self::start_of_day(calendar.period_anchor, calendar.zone, self)
+            .expect("Days start within an hour after midnight")
+            .start_of_interval(span.interval, 0, calendar);
+        let length = i64::from(span.length);
+        let index = anchor
+            .units_until(self, span.interval, calendar)
+            .div_euclid(length)
+            + i64::from(offset);
+
+        anchor.start_of_interval(span.interval, (index * length) as i32, calendar)
+    }
+    // Counts the interval units from the one that contains this timestamp to the one
+    // that contains the other
+    fn units_until(&self, other: &Self, interval: Interval, calendar: &Calendar) -> i64 {
+        use chrono::Datelike;
+
+        let (from, to) = (self.in_zone(calendar.zone), other.in_zone(calendar.zone));
+        let seconds =
+            |unit: i64| to.local_seconds().div_euclid(unit) - from.local_seconds().div_euclid(unit);
+        let (from, to) = (from.0.date_naive(), to.0.date_naive());
+        let months =
+            |date: chrono::NaiveDate| i64::from(date.year()) * 12 + i64::from(date.month0());
+        let fiscal_months = |date| months(date) - i64::from(calendar.fiscal_year_start - 1);
+
+        match interval {
+            Interval::Second => seconds(1),
+            Interval::Minute => seconds(60),
+            Interval::Hour => seconds(3600),
+            Interval::Day => (to - from).num_days(),
+            Interval::Week => {
+                (calendar.start_of_week(to) - calendar.start_of_week(from)).num_days() / 7
+            }
+            Interval::Month => months(to) - months(from),
+            Interval::Quarter => {
+                fiscal_months(to).div_euclid(3) - fiscal_months(from).div_euclid(3)
+            }
+            Interval::Year => i64::from(to.year() - from.year()),
+            Interval::FiscalYear => {
+                i64::from(calendar.fiscal_year_of(to) - calendar.fiscal_year_of(from))
+            }
+        }
+    }
+    pub fn floor_to_interval_units(&self, interval: Interval, calendar: &Calendar) -> u32 {
+        use chrono::Datelike;
+
+        let this = self.in_zone(calendar.zone);
+        let (year, date) = (this.0.year() as u32, this.0.date_naive());
         match interval {
-            Interval::Second => self.0.timestamp() as u32,
-            Interval::Minute => (self.0.timestamp() / 60) as u32,
-            Interval::Hour => (self.0.timestamp() / 3600) as u32,
-            Interval::Day => year * 10000 + self.0.month() * 100 + self.0.day(),
-            Interval::Week => year * 100 + self.0.iso_week().week(),
-            Interval::Month => year * 100 + self.0.month(),
+            Interval::Second => this.local_seconds() as u32,
+            Interval::Minute => (this.local_seconds() / 60) as u32,
+            Interval::Hour => (this.local_seconds() / 3600) as u32,
+            Interval::Day => year * 10000 + date.month() * 100 + date.day(),
+            Interval::Week => calendar.week_key(date),
+            Interval::Month => year * 100 + date.month(),
+            Interval::Quarter => {
+                let (year, quarter) = calendar.quarter_of(date);
+                year as u32 * 10 + quarter
+            }
             Interval::Year => year,
+            Interval::FiscalYear => calendar.fiscal_year_of(date) as u32,
         }
     }
 }
@@ -208,16 +511,280 @@ arg_enum! {
         Day,
         Week,
         Month,
+        Quarter,
         Year,
+        FiscalYear,
+    }
+}
+
+/// A number of interval units that records are grouped by, e.g. a week, or two weeks
+/// for sprints
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    pub length: u32,
+    pub interval: Interval,
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Span::from(Interval::default())
+    }
+}
+
+impl From<Interval> for Span {
+    fn from(interval: Interval) -> Self {
+        Span {
+            length: 1,
+            interval,
+        }
+    }
+}
+
+impl TryFrom<&str> for Span {
+    type Error = Error;
+
+    /// Parses intervals like "week", optionally preceded by a length like "2week"
+    fn try_from(string: &str) -> Result<Self> {
+        let string = string.trim();
+        let unit = string.trim_start_matches(|c: char| c.is_ascii_digit());
+
+        let length = match &string[..string.len() - unit.len()] {
+            "" => 1,
+            length => length
+                .parse()
+                .map_err(|_| Error::from(ErrorKind::InvalidTimeInterval))?,
+        };
+        // Both "2week" and "2weeks" are fine
+        let interval = unit
+            .parse::<Interval>()
+            .or_else(|_| unit.strip_suffix('s').unwrap_or(unit).parse::<Interval>())
+            .map_err(|_| Error::from(ErrorKind::InvalidTimeInterval))?;
+
+        match length {
+            0 => Err(ErrorKind::InvalidTimeInterval.into()),
+            length => Ok(Span { length, interval }),
+        }
     }
 }
 
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.length {
+            1 => write!(f, "{}", self.interval),
+            length => write!(f, "{}{}", length, self.interval),
+        }
+    }
+}
+
+// arg_enum! does not accept #[default] on variants
+#[allow(clippy::derivable_impls)]
 impl Default for Interval {
     fn default() -> Self {
         Interval::Day
     }
 }
 
+/// The timezone that records are shown and grouped in
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Zone {
+    /// The current timezone of the machine
+    #[default]
+    Local,
+    /// A timezone of the IANA database, like Europe/Berlin
+    Named(chrono_tz::Tz),
+    /// The offset that each record was punched with
+    Recorded,
+}
+
+impl TryFrom<&str> for Zone {
+    type Error = Error;
+
+    /// Parses IANA timezone names like "Europe/Berlin" or "UTC", or "local"
+    fn try_from(string: &str) -> Result<Self> {
+        match string.trim() {
+            name if name.eq_ignore_ascii_case("local") => Ok(Zone::Local),
+            name => name
+                .parse()
+                .map(Zone::Named)
+                .map_err(|_| ErrorKind::InvalidTimezone(name.into()).into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WeekStart {
+    Monday,
+    Sunday,
+    Saturday,
+}
+
+/// How the first week of a year is determined
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WeekNumbering {
+    /// Week 1 is the first week with at least four days in the year, as in ISO 8601.
+    /// Weeks around New Year's Day can belong to the year before or after.
+    Iso,
+    /// Week 1 is the week that contains January 1st
+    Us,
+}
+
+/// How days are grouped into weeks, years and longer periods, as configured in
+/// `~/.punch/config.toml`
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calendar {
+    pub week_start: WeekStart,
+    pub week_numbering: WeekNumbering,
+    /// The month that fiscal years start in, from 1 for January to 12 for December
+    pub fiscal_year_start: u32,
+    /// Spans of several units, like two-week sprints, start on this day and repeat
+    /// before and after it
+    pub period_anchor: chrono::NaiveDate,
+    /// Days start at midnight in this zone. Set per card or on the command line.
+    #[serde(skip)]
+    pub zone: Zone,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Calendar {
+            week_start: WeekStart::Monday,
+            week_numbering: WeekNumbering::Iso,
+            fiscal_year_start: 1,
+            period_anchor: chrono::NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
+            zone: Zone::default(),
+        }
+    }
+}
+
+impl Calendar {
+    /// Returns the year that the fiscal year containing the date starts in
+    pub fn fiscal_year_of(&self, date: chrono::NaiveDate) -> i32 {
+        use chrono::Datelike;
+
+        match date.month() >= self.fiscal_year_start {
+            true => date.year(),
+            false => date.year() - 1,
+        }
+    }
+    pub fn start_of_fiscal_year(&self, year: i32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(year, self.fiscal_year_start, 1)
+            .expect("Fiscal years start in a month from 1 to 12")
+    }
+    /// Returns the fiscal year and the quarter from 1 to 4 that contain the date.
+    /// Quarters start with the fiscal year, so they are calendar quarters by default.
+    pub fn quarter_of(&self, date: chrono::NaiveDate) -> (i32, u32) {
+        use chrono::Datelike;
+
+        let months_into_year = (date.month0() + 13 - self.fiscal_year_start) % 12;
+        (self.fiscal_year_of(date), months_into_year / 3 + 1)
+    }
+    pub fn start_of_quarter(&self, date: chrono::NaiveDate) -> chrono::NaiveDate {
+        let (year, quarter) = self.quarter_of(date);
+        self.start_of_fiscal_year(year)
+            .checked_add_months(chrono::Months::new(3 * (quarter - 1)))
+            .expect("Date out of range")
+    }
+    pub fn start_of_week(&self, date: chrono::NaiveDate) -> chrono::NaiveDate {
+        use chrono::Datelike;
+
+        let first_weekday = match self.week_start {
+            WeekStart::Monday => chrono::Weekday::Mon,
+            WeekStart::Sunday => chrono::Weekday::Sun,
+            WeekStart::Saturday => chrono::Weekday::Sat,
+        };
+        let days_since_start =
+            (7 + date.weekday().num_days_from_monday() - first_weekday.num_days_from_monday()) % 7;
+
+        date - chrono::Duration::days(days_since_start.into())
+    }
+    /// Returns the year and number of the week that contains the date. The year is the
+    /// year that week 1 belongs to, which differs from the year of the date around New
+    /// Year's Day.
+    pub fn week_of(&self, date: chrono::NaiveDate) -> (i32, u32) {
+        use chrono::Datelike;
+
+        // A week belongs to the year of this day of the week
+        let days_into_week = match self.week_numbering {
+            WeekNumbering::Iso => 3,
+            WeekNumbering::Us => 6,
+        };
+
+        let start = self.start_of_week(date);
+        let year = (start + chrono::Duration::days(days_into_week)).year();
+        let first_week_day = chrono::NaiveDate::from_ymd_opt(year, 1, 7 - days_into_week as u32)
+            .expect("Date out of range");
+        let first_week = self.start_of_week(first_week_day);
+
+        (year, ((start - first_week).num_days() / 7 + 1) as u32)
+    }
+    /// Sorts weeks chronologically, e.g. 202053 for week 53 of 2020
+    pub fn week_key(&self, date: chrono::NaiveDate) -> u32 {
+        let (year, week) = self.week_of(date);
+
+        year as u32 * 100 + week
+    }
+}
+
+/// A named range to show, like "today" or "last-month", along with the interval its
+/// records are grouped by
+#[derive(Clone, Copy, Debug)]
+pub struct Period(Interval, i32, pub Interval);
+
+impl Period {
+    pub const NAMES: [&'static str; 12] = [
+        "today",
+        "yesterday",
+        "this-week",
+        "last-week",
+        "this-month",
+        "last-month",
+        "this-quarter",
+        "last-quarter",
+        "this-year",
+        "last-year",
+        "this-fiscal-year",
+        "last-fiscal-year",
+    ];
+
+    /// Returns the start of the period, and its end if it is over
+    pub fn bounds(&self, calendar: &Calendar) -> (Timestamp, Option<Timestamp>) {
+        self.bounds_at(Timestamp::now(), calendar)
+    }
+    fn bounds_at(&self, now: Timestamp, calendar: &Calendar) -> (Timestamp, Option<Timestamp>) {
+        let end = match self.1 {
+            offset if offset < 0 => Some(now.start_of_interval(self.0, offset + 1, calendar)),
+            _ => None,
+        };
+
+        (now.start_of_interval(self.0, self.1, calendar), end)
+    }
+}
+
+impl TryFrom<&str> for Period {
+    type Error = Error;
+
+    fn try_from(string: &str) -> Result<Self> {
+        match string.to_lowercase().as_str() {
+            "today" => Ok(Period(Interval::Day, 0, Interval::Day)),
+            "yesterday" => Ok(Period(Interval::Day, -1, Interval::Day)),
+            "this-week" => Ok(Period(Interval::Week, 0, Interval::Day)),
+            "last-week" => Ok(Period(Interval::Week, -1, Interval::Day)),
+            "this-month" => Ok(Period(Interval::Month, 0, Interval::Week)),
+            "last-month" => Ok(Period(Interval::Month, -1, Interval::Week)),
+            "this-quarter" => Ok(Period(Interval::Quarter, 0, Interval::Month)),
+            "last-quarter" => Ok(Period(Interval::Quarter, -1, Interval::Month)),
+            "this-year" => Ok(Period(Interval::Year, 0, Interval::Month)),
+            "last-year" => Ok(Period(Interval::Year, -1, Interval::Month)),
+            "this-fiscal-year" => Ok(Period(Interval::FiscalYear, 0, Interval::Month)),
+            "last-fiscal-year" => Ok(Period(Interval::FiscalYear, -1, Interval::Month)),
+            _ => Err(ErrorKind::InvalidTimeInterval.into()),
+        }
+    }
+}
+
 impl TryFrom<&str> for Interval {
     type Error = Error;
 
@@ -231,3 +798,431 @@ impl TryFrom<&str> for Interval {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(string: &str) -> chrono::NaiveDate {
+        chrono::NaiveDate::parse_from_str(string, "%F").unwrap()
+    }
+
+    fn calendar(week_start: WeekStart, week_numbering: WeekNumbering) -> Calendar {
+        Calendar {
+            week_start,
+            week_numbering,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn iso_weeks_around_new_year() {
+        let iso = Calendar::default();
+
+        assert_eq!(iso.week_of(date("2020-12-31")), (2020, 53));
+        assert_eq!(iso.week_of(date("2021-01-03")), (2020, 53));
+        assert_eq!(iso.week_of(date("2021-01-04")), (2021, 1));
+        assert_eq!(iso.week_of(date("2024-12-30")), (2025, 1));
+        assert_eq!(iso.week_of(date("2026-01-01")), (2026, 1));
+        assert_eq!(iso.week_of(date("2027-01-01")), (2026, 53));
+    }
+
+    #[test]
+    fn us_weeks_around_new_year() {
+        let us = calendar(WeekStart::Sunday, WeekNumbering::Us);
+
+        assert_eq!(us.week_of(date("2021-12-26")), (2022, 1));
+        assert_eq!(us.week_of(date("2022-01-01")), (2022, 1));
+        assert_eq!(us.week_of(date("2022-01-02")), (2022, 2));
+        assert_eq!(us.week_of(date("2022-12-31")), (2022, 53));
+        assert_eq!(us.week_of(date("2023-01-01")), (2023, 1));
+        assert_eq!(us.week_of(date("2024-12-29")), (2025, 1));
+    }
+
+    #[test]
+    fn weeks_starting_on_saturday_around_new_year() {
+        let saturday = calendar(WeekStart::Saturday, WeekNumbering::Iso);
+
+        assert_eq!(saturday.week_of(date("2021-12-31")), (2021, 52));
+        assert_eq!(saturday.week_of(date("2022-01-01")), (2022, 1));
+        assert_eq!(saturday.week_of(date("2022-01-07")), (2022, 1));
+    }
+
+    #[test]
+    fn start_of_week() {
+        let sunday = date("2026-10-18");
+
+        let monday_start = calendar(WeekStart::Monday, WeekNumbering::Iso);
+        let sunday_start = calendar(WeekStart::Sunday, WeekNumbering::Us);
+        let saturday_start = calendar(WeekStart::Saturday, WeekNumbering::Us);
+
+        assert_eq!(monday_start.start_of_week(sunday), date("2026-10-12"));
+        assert_eq!(sunday_start.start_of_week(sunday), date("2026-10-18"));
+        assert_eq!(saturday_start.start_of_week(sunday), date("2026-10-17"));
+    }
+
+    #[test]
+    fn week_keys_change_with_each_week_and_increase_across_years() {
+        let starts = [WeekStart::Monday, WeekStart::Sunday, WeekStart::Saturday];
+        let numberings = [WeekNumbering::Iso, WeekNumbering::Us];
+
+        for (&week_start, &week_numbering) in starts
+            .iter()
+            .flat_map(|s| numberings.iter().map(move |n| (s, n)))
+        {
+            let calendar = calendar(week_start, week_numbering);
+            let mut day = date("2019-12-01");
+
+            while day < date("2028-02-01") {
+                let next_day = day.succ_opt().unwrap();
+                let (_, week) = calendar.week_of(day);
+                let is_same_week = calendar.start_of_week(day) == calendar.start_of_week(next_day);
+
+                assert!((1..=53).contains(&week), "{:?} {}", calendar, day);
+                if is_same_week {
+                    assert_eq!(calendar.week_key(day), calendar.week_key(next_day));
+                } else {
+                    assert!(calendar.week_key(day) < calendar.week_key(next_day));
+                }
+
+                day = next_day;
+            }
+        }
+    }
+
+    #[test]
+    fn fiscal_years_starting_in_april() {
+        let calendar = Calendar {
+            fiscal_year_start: 4,
+            ..Default::default()
+        };
+
+        assert_eq!(calendar.fiscal_year_of(date("2026-03-31")), 2025);
+        assert_eq!(calendar.fiscal_year_of(date("2026-04-01")), 2026);
+        assert_eq!(calendar.start_of_fiscal_year(2026), date("2026-04-01"));
+    }
+
+    #[test]
+    fn quarters_start_with_the_fiscal_year() {
+        let calendar = Calendar::default();
+        assert_eq!(calendar.quarter_of(date("2026-05-15")), (2026, 2));
+        assert_eq!(
+            calendar.start_of_quarter(date("2026-05-15")),
+            date("2026-04-01")
+        );
+
+        let calendar = Calendar {
+            fiscal_year_start: 4,
+            ..Default::default()
+        };
+        assert_eq!(calendar.quarter_of(date("2026-03-31")), (2025, 4));
+        assert_eq!(calendar.quarter_of(date("2026-04-01")), (2026, 1));
+        assert_eq!(calendar.quarter_of(date("2026-12-24")), (2026, 3));
+        assert_eq!(calendar.quarter_of(date("2027-01-01")), (2026, 4));
+        assert_eq!(
+            calendar.start_of_quarter(date("2027-02-28")),
+            date("2027-01-01")
+        );
+        assert_eq!(
+            calendar.start_of_quarter(date("2026-06-30")),
+            date("2026-04-01")
+        );
+    }
+
+    #[test]
+    fn spans_of_several_units() {
+        let span = Span::try_from("2weeks").unwrap();
+        assert_eq!((span.length, span.interval.to_string()), (2, "Week".into()));
+
+        let span = Span::try_from("Quarter").unwrap();
+        assert_eq!(
+            (span.length, span.interval.to_string()),
+            (1, "Quarter".into())
+        );
+
+        assert!(Span::try_from("0day").is_err());
+        assert!(Span::try_from("2fortnights").is_err());
+    }
+
+    #[test]
+    fn bounds_of_periods() {
+        // A Wednesday
+        let now = timestamp("2026-10-14T12:00:00+00:00");
+        let bounds = |period: &str, calendar: &Calendar| {
+            let (start, end) = Period::try_from(period).unwrap().bounds_at(now, calendar);
+            (start.to_rfc3339(), end.map(Timestamp::to_rfc3339))
+        };
+        let utc = in_zone("UTC");
+
+        assert_eq!(
+            bounds("yesterday", &utc),
+            (
+                "2026-10-13T00:00:00Z".into(),
+                Some("2026-10-14T00:00:00Z".into())
+            )
+        );
+        assert_eq!(
+            bounds("last-month", &utc),
+            (
+                "2026-09-01T00:00:00Z".into(),
+                Some("2026-10-01T00:00:00Z".into())
+            )
+        );
+        assert_eq!(
+            bounds("this-week", &utc),
+            ("2026-10-12T00:00:00Z".into(), None)
+        );
+
+        let calendar = Calendar {
+            week_start: WeekStart::Saturday,
+            ..utc
+        };
+        assert_eq!(
+            bounds("this-week", &calendar),
+            ("2026-10-10T00:00:00Z".into(), None)
+        );
+        assert_eq!(
+            bounds("last-week", &calendar),
+            (
+                "2026-10-03T00:00:00Z".into(),
+                Some("2026-10-10T00:00:00Z".into())
+            )
+        );
+
+        let calendar = Calendar {
+            fiscal_year_start: 4,
+            ..utc
+        };
+        assert_eq!(
+            bounds("this-quarter", &calendar),
+            ("2026-10-01T00:00:00Z".into(), None)
+        );
+        assert_eq!(
+            bounds("last-fiscal-year", &calendar),
+            (
+                "2025-04-01T00:00:00Z".into(),
+                Some("2026-04-01T00:00:00Z".into())
+            )
+        );
+
+        // Periods start at midnight in the zone of the calendar
+        assert_eq!(
+            bounds("last-month", &in_zone("Europe/Berlin")),
+            (
+                "2026-09-01T00:00:00+02:00".into(),
+                Some("2026-10-01T00:00:00+02:00".into())
+            )
+        );
+    }
+
+    #[test]
+    fn starts_of_the_last_spans() {
+        // As `--last N` does, a Wednesday
+        let now = timestamp("2026-10-14T12:00:00+00:00");
+        let since = |n: i32, span: &str, calendar: &Calendar| {
+            let span = Span::try_from(span).unwrap();
+            now.start_of_span(span, 1 - n, calendar).to_rfc3339()
+        };
+        let utc = in_zone("UTC");
+
+        assert_eq!(since(1, "week", &utc), "2026-10-12T00:00:00Z");
+        assert_eq!(since(2, "week", &utc), "2026-10-05T00:00:00Z");
+        let calendar = Calendar {
+            week_start: WeekStart::Sunday,
+            ..utc
+        };
+        assert_eq!(since(2, "week", &calendar), "2026-10-04T00:00:00Z");
+
+        assert_eq!(since(3, "quarter", &utc), "2026-04-01T00:00:00Z");
+        assert_eq!(since(2, "fiscalyear", &utc), "2025-01-01T00:00:00Z");
+        let calendar = Calendar {
+            fiscal_year_start: 2,
+            ..utc
+        };
+        assert_eq!(since(3, "quarter", &calendar), "2026-02-01T00:00:00Z");
+        assert_eq!(since(1, "fiscalyear", &calendar), "2026-02-01T00:00:00Z");
+        assert_eq!(since(2, "fiscalyear", &calendar), "2025-02-01T00:00:00Z");
+    }
+
+    fn timestamp(string: &str) -> Timestamp {
+        Timestamp::try_from(string).unwrap()
+    }
+
+    fn in_zone(zone: &str) -> Calendar {
+        Calendar {
+            zone: Zone::try_from(zone).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn durations() {
+        let seconds = |string| Duration::try_from(string).unwrap().in_seconds();
+
+        assert_eq!(seconds("15min"), 900);
+        assert_eq!(seconds("2h"), 7200);
+        assert_eq!(seconds("1h30m"), 5400);
+        assert_eq!(seconds(" 1d2h "), 93600);
+        assert_eq!(seconds("1w"), 604800);
+
+        for invalid in [
+            "",
+            "15",
+            "min",
+            "15s",
+            "1.5h",
+            "-15min",
+            "1h 30m",
+            "2fortnights",
+        ] {
+            assert!(Duration::try_from(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn times_of_punches() {
+        use chrono::Datelike;
+
+        let berlin = Zone::try_from("Europe/Berlin").unwrap();
+        let now = Timestamp::now().in_zone(berlin);
+        let today = now.date_naive();
+        let at = |string| Timestamp::parse_in(string, berlin).unwrap();
+
+        assert_eq!(at("09:15").format("%F %R"), format!("{} 09:15", today));
+        assert_eq!(
+            at("09:15:30").format("%F %T"),
+            format!("{} 09:15:30", today)
+        );
+        assert_eq!(
+            at("yesterday 17:30").format("%F %R"),
+            format!("{} 17:30", today.pred_opt().unwrap())
+        );
+        assert_eq!(at("today").format("%F %T"), format!("{} 00:00:00", today));
+
+        // Weekdays refer to the last one up to today
+        let monday = at("Monday 08:00").date_naive();
+        assert_eq!(monday.weekday(), chrono::Weekday::Mon);
+        assert!(monday <= today && today - monday < chrono::Duration::days(7));
+
+        // Timestamps with offset are kept as they are
+        assert_eq!(
+            at("2026-02-14T09:15:00-05:00").to_rfc3339(),
+            "2026-02-14T09:15:00-05:00"
+        );
+        assert_eq!(
+            at("2026-02-14 09:15").to_rfc3339(),
+            "2026-02-14T09:15:00+01:00"
+        );
+
+        let ago = Duration::between(at("-1h30m"), Timestamp::now());
+        assert!((5400..5410).contains(&ago.in_seconds()));
+
+        for invalid in [
+            "",
+            "25:00",
+            "9.15",
+            "someday 09:15",
+            "2026-02-30",
+            "2026-02-14T09:15:00",
+            "-2fortnights",
+            "tomorrow",
+        ] {
+            assert!(Timestamp::parse_in(invalid, berlin).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn times_in_a_zone() {
+        let berlin = Zone::try_from("Europe/Berlin").unwrap();
+        let tokyo = Zone::try_from("Asia/Tokyo").unwrap();
+
+        let start = Timestamp::parse_in("2026-03-01 09:15", berlin).unwrap();
+        assert_eq!(start.to_rfc3339(), "2026-03-01T09:15:00+01:00");
+        let start = Timestamp::parse_in("2026-07-01", tokyo).unwrap();
+        assert_eq!(start.to_rfc3339(), "2026-07-01T00:00:00+09:00");
+
+        // Skipped by the DST transition in Berlin
+        assert!(Timestamp::parse_in("2026-03-29 02:30", berlin).is_err());
+    }
+
+    #[test]
+    fn days_around_dst_transitions() {
+        let berlin = in_zone("Europe/Berlin");
+        let span = Span::from(Interval::Day);
+
+        // The last Sunday of October has 25 hours in Berlin
+        let noon = timestamp("2026-10-25T12:00:00+01:00");
+        let start = noon.start_of_span(span, 0, &berlin);
+        let end = noon.start_of_span(span, 1, &berlin);
+        assert_eq!(start.to_rfc3339(), "2026-10-25T00:00:00+02:00");
+        assert_eq!(end.to_rfc3339(), "2026-10-26T00:00:00+01:00");
+        assert_eq!(Duration::between(start, end).0, chrono::Duration::hours(25));
+
+        // ... and the last Sunday of March 23 hours
+        let noon = timestamp("2026-03-29T12:00:00+02:00");
+        let start = noon.start_of_span(span, 0, &berlin);
+        let end = noon.start_of_span(span, 1, &berlin);
+        assert_eq!(Duration::between(start, end).0, chrono::Duration::hours(23));
+        assert_eq!(start.units_until(&end, Interval::Day, &berlin), 1);
+
+        // In Havana, midnight is skipped and the day starts an hour later
+        let havana = in_zone("America/Havana");
+        let noon = timestamp("2026-03-08T12:00:00-04:00");
+        let start = noon.start_of_span(span, 0, &havana);
+        assert_eq!(start.to_rfc3339(), "2026-03-08T01:00:00-04:00");
+    }
+
+    #[test]
+    fn days_in_zones_and_recorded_offsets() {
+        let late_in_tokyo = timestamp("2026-10-15T23:30:00+09:00");
+        let day = Interval::Day;
+
+        let utc = in_zone("UTC");
+        assert_eq!(late_in_tokyo.floor_to_interval_units(day, &utc), 20261015);
+        let tokyo = in_zone("Asia/Tokyo");
+        assert_eq!(late_in_tokyo.floor_to_interval_units(day, &tokyo), 20261015);
+        let new_york = in_zone("America/New_York");
+        assert_eq!(
+            late_in_tokyo.floor_to_interval_units(day, &new_york),
+            20261015
+        );
+        let recorded = Calendar {
+            zone: Zone::Recorded,
+            ..Default::default()
+        };
+        assert_eq!(
+            late_in_tokyo.floor_to_interval_units(day, &recorded),
+            20261015
+        );
+        let hour = Interval::Hour;
+        assert_eq!(
+            late_in_tokyo
+                .start_of_interval(hour, 0, &recorded)
+                .to_rfc3339(),
+            "2026-10-15T23:00:00+09:00"
+        );
+
+        let early_in_tokyo = timestamp("2026-10-16T00:30:00+09:00");
+        assert_eq!(early_in_tokyo.floor_to_interval_units(day, &utc), 20261015);
+        assert_eq!(
+            early_in_tokyo.floor_to_interval_units(day, &recorded),
+            20261016
+        );
+        assert_eq!(
+            early_in_tokyo
+                .start_of_interval(day, 0, &recorded)
+                .to_rfc3339(),
+            "2026-10-16T00:00:00+09:00"
+        );
+    }
+
+    #[test]
+    fn timezone_names() {
+        assert!(matches!(Zone::try_from("Local"), Ok(Zone::Local)));
+        assert!(matches!(
+            Zone::try_from("Europe/Berlin"),
+            Ok(Zone::Named(_))
+        ));
+        assert!(Zone::try_from("Mars/Olympus_Mons").is_err());
+    }
+}